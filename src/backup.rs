@@ -0,0 +1,76 @@
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+use crate::config::{BackupMode, Config};
+
+///Copies `path` aside before it gets overwritten, as configured by `config.backup`.
+///Nothing is done if the file doesn't exist yet.
+pub fn backup(path: &Path, config: &Config) -> Result<(), Error> {
+    if !path.is_file() {
+        return Ok(());
+    }
+    match config.backup {
+        BackupMode::Off => {},
+        BackupMode::Simple => {
+            fs::copy(path, simple_backup_path(path))?;
+        },
+        BackupMode::Numbered => {
+            fs::create_dir_all(&config.backup_dir)?;
+            let existing = numbered_backups(path, config);
+            let next = existing.first().map_or(1, |(n, _)| n + 1);
+            let prefix = numbered_prefix(path);
+            fs::copy(path, config.backup_dir.join(format!("{}.~{}~", prefix, next)))?;
+            //existing is sorted newest first, and one more copy was just added
+            for (_, old) in existing.iter().skip(config.backup_count.saturating_sub(1)) {
+                fs::remove_file(old)?;
+            }
+        },
+    }
+    Ok(())
+}
+
+///Lists the backups of `path` that exist on disk, newest first. The `file~` sibling, if
+///any, comes before the numbered copies.
+pub fn list(path: &Path, config: &Config) -> Vec<PathBuf> {
+    let mut backups = Vec::new();
+    let simple = simple_backup_path(path);
+    if simple.is_file() {
+        backups.push(simple);
+    }
+    backups.extend(numbered_backups(path, config).into_iter().map(|(_, p)| p));
+    backups
+}
+
+fn simple_backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push("~");
+    path.with_file_name(name)
+}
+
+///Name under which the backups of `path` are kept in the shared backup directory. The
+///whole path is encoded in it, so that files with the same name in different projects
+///don't overwrite each other's backups.
+fn numbered_prefix(path: &Path) -> String {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    path.to_string_lossy().replace('/', "!")
+}
+
+fn numbered_backups(path: &Path, config: &Config) -> Vec<(usize, PathBuf)> {
+    let prefix = format!("{}.~", numbered_prefix(path));
+    let mut backups = Vec::new();
+    if let Ok(entries) = fs::read_dir(&config.backup_dir) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let number = name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix('~'))
+                .and_then(|n| n.parse::<usize>().ok());
+            if let Some(number) = number {
+                backups.push((number, entry.path()));
+            }
+        }
+    }
+    backups.sort_by_key(|(number, _)| std::cmp::Reverse(*number));
+    backups
+}
@@ -0,0 +1,106 @@
+use std::fs;
+use std::io::{Error, Read, Write};
+use std::path::Path;
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xB5, 0x2F, 0xFD];
+const XZ_MAGIC: &[u8] = &[0xFD, b'7', b'z', b'X', b'Z', 0x00];
+const XZ_LEVEL: u32 = 6;
+
+///Compression format of a file on disk. Compressed files are decompressed into the buffer
+///when opened and compressed again with the same format when saved.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    ///Recognizes a compressed file by its first bytes, so that e.g. rotated logs named
+    ///`app.log.1` are handled whatever their name.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if bytes.starts_with(ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else if bytes.starts_with(XZ_MAGIC) {
+            Some(Compression::Xz)
+        } else {
+            None
+        }
+    }
+
+    ///Looks at the first bytes of the file at `path`.
+    pub fn sniff(path: &str) -> Result<Option<Self>, Error> {
+        let mut magic = Vec::with_capacity(XZ_MAGIC.len());
+        fs::File::open(path)?.take(XZ_MAGIC.len() as u64).read_to_end(&mut magic)?;
+        Ok(Self::detect(&magic))
+    }
+
+    ///Format implied by the extension of `filename`, used for files that don't exist yet.
+    pub fn from_filename(filename: &str) -> Option<Self> {
+        match Path::new(filename).extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            "xz" => Some(Compression::Xz),
+            _ => None,
+        }
+    }
+
+    ///Name of the file inside the archive, e.g. `foo.rs` for `foo.rs.gz`.
+    pub fn strip_extension(filename: &str) -> &str {
+        match Self::from_filename(filename) {
+            Some(_) => Path::new(filename)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or(filename),
+            None => filename,
+        }
+    }
+
+    pub fn to_enum_str(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+            Compression::Xz => "xz",
+        }
+    }
+
+    pub fn decompress(self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut result = Vec::new();
+        match self {
+            Compression::Gzip => {
+                MultiGzDecoder::new(bytes).read_to_end(&mut result)?;
+            },
+            Compression::Zstd => {
+                result = zstd::decode_all(bytes)?;
+            },
+            Compression::Xz => {
+                XzDecoder::new(bytes).read_to_end(&mut result)?;
+            },
+        }
+        Ok(result)
+    }
+
+    pub fn compress(self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            },
+            Compression::Zstd => zstd::encode_all(bytes, zstd::DEFAULT_COMPRESSION_LEVEL),
+            Compression::Xz => {
+                let mut encoder = XzEncoder::new(Vec::new(), XZ_LEVEL);
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            },
+        }
+    }
+}
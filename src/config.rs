@@ -0,0 +1,433 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::editorconfig;
+use crate::encoding::Encoding;
+use crate::file::{HighlightOptions, LineEnding};
+
+const CONFIG_FILENAME: &str = ".hectorc";
+const DEFAULT_BACKUP_COUNT: usize = 10;
+const DEFAULT_LARGE_FILE_SIZE: u64 = 64 * 1024 * 1024;
+const DEFAULT_HIGHLIGHT_MAX_SIZE: u64 = 8 * 1024 * 1024;
+const DEFAULT_TAB_WIDTH: usize = 4;
+
+///How `File::save` keeps the previous version of a file around.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum BackupMode {
+    #[default]
+    Off,
+    ///A single `file~` copy next to the file.
+    Simple,
+    ///Numbered `file.~N~` copies in the backup directory.
+    Numbered,
+}
+
+impl BackupMode {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off" | "none" | "false" => Some(BackupMode::Off),
+            "simple" | "true" => Some(BackupMode::Simple),
+            "numbered" => Some(BackupMode::Numbered),
+            _ => None,
+        }
+    }
+}
+
+///Whether lines are indented with spaces or with tabs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IndentStyle {
+    Spaces,
+    Tabs,
+}
+
+impl IndentStyle {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "spaces" | "space" => Some(IndentStyle::Spaces),
+            "tabs" | "tab" => Some(IndentStyle::Tabs),
+            _ => None,
+        }
+    }
+}
+
+///What the gutter to the left of the rows shows.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum LineNumbers {
+    #[default]
+    Off,
+    Absolute,
+    ///Distance from the cursor's line.
+    Relative,
+    ///Relative, except for the cursor's line which gets its absolute number.
+    Hybrid,
+}
+
+impl LineNumbers {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off" | "none" | "false" => Some(LineNumbers::Off),
+            "absolute" | "on" | "true" => Some(LineNumbers::Absolute),
+            "relative" => Some(LineNumbers::Relative),
+            "hybrid" => Some(LineNumbers::Hybrid),
+            _ => None,
+        }
+    }
+
+    pub fn to_str(self) -> &'static str {
+        match self {
+            LineNumbers::Off => "off",
+            LineNumbers::Absolute => "absolute",
+            LineNumbers::Relative => "relative",
+            LineNumbers::Hybrid => "hybrid",
+        }
+    }
+
+    ///The mode after this one, for cycling through them.
+    pub fn next(self) -> Self {
+        match self {
+            LineNumbers::Off => LineNumbers::Absolute,
+            LineNumbers::Absolute => LineNumbers::Relative,
+            LineNumbers::Relative => LineNumbers::Hybrid,
+            LineNumbers::Hybrid => LineNumbers::Off,
+        }
+    }
+}
+
+///How rows too long for the window are shown.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum SoftWrap {
+    ///The view scrolls sideways.
+    #[default]
+    Off,
+    ///Rows are wrapped at the width of the window.
+    Anywhere,
+    ///Rows are wrapped after the last separator that fits, when there is one.
+    Words,
+}
+
+impl SoftWrap {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off" | "none" | "false" => Some(SoftWrap::Off),
+            "on" | "true" | "anywhere" => Some(SoftWrap::Anywhere),
+            "words" | "word" => Some(SoftWrap::Words),
+            _ => None,
+        }
+    }
+
+    pub fn to_str(self) -> &'static str {
+        match self {
+            SoftWrap::Off => "off",
+            SoftWrap::Anywhere => "on",
+            SoftWrap::Words => "words",
+        }
+    }
+
+    ///The mode after this one, for cycling through them.
+    pub fn next(self) -> Self {
+        match self {
+            SoftWrap::Off => SoftWrap::Anywhere,
+            SoftWrap::Anywhere => SoftWrap::Words,
+            SoftWrap::Words => SoftWrap::Off,
+        }
+    }
+}
+
+///Editor settings, read from `~/.hectorc`, then from the `.editorconfig` files and the
+///nearest `.hectorc` in the directories above the opened file, so that a project can
+///override the user's defaults.
+///
+///The files are made of `key = value` lines. Lines starting with `#` or `;` are comments,
+///and settings under a `[section]` header named after a file type, e.g. `[rust]`, only
+///apply to files of that type.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub backup: BackupMode,
+    pub backup_dir: PathBuf,
+    pub backup_count: usize,
+    ///Files bigger than this many bytes are opened in large file mode.
+    pub large_file_size: u64,
+    ///Files bigger than this many bytes are not syntax highlighted.
+    pub highlight_max_size: u64,
+    ///Columns between tab stops.
+    pub tab_width: usize,
+    ///What new indentation is made of, by default a copy of the line above.
+    pub indent_style: Option<IndentStyle>,
+    ///Columns per indentation level when indenting with spaces, `tab_width` if not set.
+    pub indent_size: Option<usize>,
+    ///Line endings of files that are opened, instead of the detected ones.
+    pub end_of_line: Option<LineEnding>,
+    ///Encoding of files that are opened, instead of the detected one.
+    pub charset: Option<Encoding>,
+    ///Save hook removing whitespace at the end of lines.
+    pub trim_trailing_whitespace: bool,
+    ///Save hook making the file end with exactly one newline.
+    pub ensure_final_newline: bool,
+    ///Save hook converting leading tabs to spaces or the reverse.
+    pub convert_indent: Option<IndentStyle>,
+    ///Line number gutter shown when the file is opened.
+    pub line_numbers: LineNumbers,
+    ///Whether long rows are wrapped when the file is opened.
+    pub soft_wrap: SoftWrap,
+    ///Whether whitespace is drawn with visible glyphs when the file is opened.
+    pub show_whitespace: bool,
+    ///Whether the background of the cursor's row is highlighted.
+    pub highlight_current_line: bool,
+    ///Columns, counted from 1, marked with a vertical ruler.
+    pub rulers: Vec<usize>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            backup: BackupMode::Off,
+            backup_dir: home_dir().join(".hecto").join("backups"),
+            backup_count: DEFAULT_BACKUP_COUNT,
+            large_file_size: DEFAULT_LARGE_FILE_SIZE,
+            highlight_max_size: DEFAULT_HIGHLIGHT_MAX_SIZE,
+            tab_width: DEFAULT_TAB_WIDTH,
+            indent_style: None,
+            indent_size: None,
+            end_of_line: None,
+            charset: None,
+            trim_trailing_whitespace: false,
+            ensure_final_newline: false,
+            convert_indent: None,
+            line_numbers: LineNumbers::Off,
+            soft_wrap: SoftWrap::Off,
+            show_whitespace: false,
+            highlight_current_line: false,
+            rulers: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    ///Loads the settings that apply to `filename`, or only the user's settings for a buffer
+    ///without a file.
+    pub fn load(filename: Option<&str>) -> Self {
+        let mut config = Self::default();
+        let file_type = filename
+            .and_then(HighlightOptions::set_filetype)
+            .map(|file_type| file_type.to_enum_str());
+        config.read(&home_dir().join(CONFIG_FILENAME), file_type);
+        if let Some(filename) = filename {
+            for (key, value) in editorconfig::properties(Path::new(filename)) {
+                config.set_editorconfig(&key, &value);
+            }
+        }
+        if let Some(project) = filename.and_then(|name| find_project_config(Path::new(name))) {
+            if project != home_dir().join(CONFIG_FILENAME) {
+                config.read(&project, file_type);
+            }
+        }
+        config
+    }
+
+    ///Reads the settings in the config file at `path` that apply to files of `file_type`.
+    fn read(&mut self, path: &Path, file_type: Option<&str>) {
+        if let Ok(contents) = fs::read_to_string(path) {
+            for (section, key, value) in parse_ini(&contents) {
+                let applies = section.is_empty()
+                    || file_type.is_some_and(|file_type| section.eq_ignore_ascii_case(file_type));
+                if applies {
+                    self.set(&key, &value);
+                }
+            }
+        }
+    }
+
+    ///Applies a single setting. Unknown keys and invalid values are ignored, so that a typo
+    ///in a config file doesn't stop the editor from starting.
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "backup" => {
+                if let Some(mode) = BackupMode::from_name(value) {
+                    self.backup = mode;
+                }
+            },
+            "backup_dir" => self.backup_dir = expand_home(value),
+            "backup_count" => {
+                if let Ok(count) = value.parse() {
+                    self.backup_count = count;
+                }
+            },
+            "large_file_size" => {
+                if let Some(size) = parse_size(value) {
+                    self.large_file_size = size;
+                }
+            },
+            "highlight_max_size" => {
+                if let Some(size) = parse_size(value) {
+                    self.highlight_max_size = size;
+                }
+            },
+            "tab_width" => {
+                match value.parse() {
+                    Ok(width) if width > 0 => self.tab_width = width,
+                    _ => {},
+                }
+            },
+            "trim_trailing_whitespace" => {
+                if let Some(enabled) = parse_bool(value) {
+                    self.trim_trailing_whitespace = enabled;
+                }
+            },
+            "indent_style" => {
+                if let Some(style) = IndentStyle::from_name(value) {
+                    self.indent_style = Some(style);
+                }
+            },
+            "indent_size" => {
+                match value.parse() {
+                    Ok(size) if size > 0 => self.indent_size = Some(size),
+                    _ if value == "tab" => self.indent_size = None,
+                    _ => {},
+                }
+            },
+            "end_of_line" => {
+                if let Some(line_ending) = LineEnding::from_name(value) {
+                    self.end_of_line = Some(line_ending);
+                }
+            },
+            "charset" => {
+                if let Some(encoding) = Encoding::from_name(value) {
+                    self.charset = Some(encoding);
+                }
+            },
+            "line_numbers" => {
+                if let Some(mode) = LineNumbers::from_name(value) {
+                    self.line_numbers = mode;
+                }
+            },
+            "soft_wrap" => {
+                if let Some(mode) = SoftWrap::from_name(value) {
+                    self.soft_wrap = mode;
+                }
+            },
+            "show_whitespace" => {
+                if let Some(enabled) = parse_bool(value) {
+                    self.show_whitespace = enabled;
+                }
+            },
+            "highlight_current_line" => {
+                if let Some(enabled) = parse_bool(value) {
+                    self.highlight_current_line = enabled;
+                }
+            },
+            "rulers" => self.rulers = parse_columns(value),
+            "ensure_final_newline" => {
+                if let Some(enabled) = parse_bool(value) {
+                    self.ensure_final_newline = enabled;
+                }
+            },
+            "convert_indent" => {
+                if let Some(style) = IndentStyle::from_name(value) {
+                    self.convert_indent = Some(style);
+                } else if parse_bool(value) == Some(false) {
+                    self.convert_indent = None;
+                }
+            },
+            _ => {},
+        }
+    }
+
+    ///Applies a property from an `.editorconfig`. Only the standard EditorConfig properties
+    ///are mapped to settings, a repository can't change anything else through it.
+    fn set_editorconfig(&mut self, key: &str, value: &str) {
+        match key {
+            "indent_style" | "indent_size" | "tab_width" | "end_of_line" | "charset"
+                | "trim_trailing_whitespace" => self.set(key, value),
+            "insert_final_newline" => self.set("ensure_final_newline", value),
+            //the ruler goes right after the longest allowed line
+            "max_line_length" => {
+                if let Ok(length) = value.parse::<usize>() {
+                    self.rulers = vec![length + 1];
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+///Splits an INI style file into `(section, key, value)` triples. Keys are lowercased,
+///and settings before the first header have an empty section.
+pub fn parse_ini(contents: &str) -> Vec<(String, String, String)> {
+    let mut settings = Vec::new();
+    let mut section = String::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = header.trim().to_string();
+        } else if let Some((key, value)) = line.split_once('=') {
+            settings.push((
+                section.clone(),
+                key.trim().to_ascii_lowercase(),
+                value.trim().to_string(),
+            ));
+        }
+    }
+    settings
+}
+
+///Parses a byte count with an optional `K`, `M` or `G` suffix, e.g. `64M`.
+fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (number, multiplier) = match value.chars().last()?.to_ascii_uppercase() {
+        'K' => (&value[..value.len() - 1], 1024),
+        'M' => (&value[..value.len() - 1], 1024 * 1024),
+        'G' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    number.trim().parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+///Parses a list of columns such as `80, 100`. Anything that is not a column, e.g. `off`,
+///is left out.
+pub fn parse_columns(value: &str) -> Vec<usize> {
+    value.split(|c: char| c == ',' || c.is_whitespace())
+        .filter_map(|column| column.parse().ok())
+        .filter(|&column| column > 0)
+        .collect()
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Some(true),
+        "false" | "no" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn find_project_config(path: &Path) -> Option<PathBuf> {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir().ok()?.join(path)
+    };
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        let candidate = current.join(CONFIG_FILENAME);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        dir = current.parent();
+    }
+    None
+}
+
+pub fn home_dir() -> PathBuf {
+    env::var_os("HOME").map_or_else(|| PathBuf::from("."), PathBuf::from)
+}
+
+fn expand_home(value: &str) -> PathBuf {
+    if let Some(rest) = value.strip_prefix("~/") {
+        home_dir().join(rest)
+    } else {
+        PathBuf::from(value)
+    }
+}
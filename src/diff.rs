@@ -0,0 +1,103 @@
+const CONTEXT_LINES: usize = 3;
+//the lcs table grows with the product of both sides, past this we give up on aligning them
+const MAX_TABLE_SIZE: usize = 4_000_000;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Op {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+///Compares two texts line by line and returns a unified style listing of the changed
+///lines, with a few lines of context around each change. The listing is empty if both
+///texts are the same.
+pub fn diff(old: &[&str], new: &[&str]) -> Vec<String> {
+    let ops = edit_script(old, new);
+    let changed: Vec<bool> = ops.iter().map(|op| !matches!(op, Op::Equal(..))).collect();
+    let mut lines = Vec::new();
+    let mut last_shown: Option<usize> = None;
+    for (index, op) in ops.iter().enumerate() {
+        let start = index.saturating_sub(CONTEXT_LINES);
+        let end = (index + CONTEXT_LINES + 1).min(ops.len());
+        if !changed[start..end].iter().any(|&c| c) {
+            continue;
+        }
+        if last_shown.is_none_or(|last| last + 1 != index) {
+            let (old_line, new_line) = line_numbers(&ops, index);
+            lines.push(format!("@@ -{} +{} @@", old_line + 1, new_line + 1));
+        }
+        last_shown = Some(index);
+        lines.push(match *op {
+            Op::Equal(i, _) => format!("  {}", old[i]),
+            Op::Delete(i) => format!("- {}", old[i]),
+            Op::Insert(j) => format!("+ {}", new[j]),
+        });
+    }
+    lines
+}
+
+///Line numbers in the old and new text at which the operation at `index` applies.
+fn line_numbers(ops: &[Op], index: usize) -> (usize, usize) {
+    let mut old_line = 0;
+    let mut new_line = 0;
+    for op in &ops[..index] {
+        match op {
+            Op::Equal(..) => {
+                old_line += 1;
+                new_line += 1;
+            },
+            Op::Delete(_) => old_line += 1,
+            Op::Insert(_) => new_line += 1,
+        }
+    }
+    (old_line, new_line)
+}
+
+fn edit_script(old: &[&str], new: &[&str]) -> Vec<Op> {
+    //most diffs only touch a small part of the file, so skip the common ends first
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut ops: Vec<Op> = (0..prefix).map(|i| Op::Equal(i, i)).collect();
+    if a.len().saturating_mul(b.len()) > MAX_TABLE_SIZE {
+        ops.extend((0..a.len()).map(|i| Op::Delete(prefix + i)));
+        ops.extend((0..b.len()).map(|j| Op::Insert(prefix + j)));
+    } else {
+        //lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+        let width = b.len() + 1;
+        let mut lcs = vec![0u32; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * width + j] = if a[i] == b[j] {
+                    lcs[(i + 1) * width + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                ops.push(Op::Equal(prefix + i, prefix + j));
+                i += 1;
+                j += 1;
+            } else if i < a.len() && (j == b.len() || lcs[(i + 1) * width + j] >= lcs[i * width + j + 1]) {
+                ops.push(Op::Delete(prefix + i));
+                i += 1;
+            } else {
+                ops.push(Op::Insert(prefix + j));
+                j += 1;
+            }
+        }
+    }
+    let old_tail = old.len() - suffix;
+    let new_tail = new.len() - suffix;
+    ops.extend((0..suffix).map(|k| Op::Equal(old_tail + k, new_tail + k)));
+    ops
+}
@@ -1,10 +1,19 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
 use std::time::{self, Duration};
+use termion::color;
 use termion::event::Key;
-use std::error;
 
-use crate::row::Row;
-use crate::file::File;
-use crate::screen::{Screen, Position};
+use crate::row::{Backgrounds, Row};
+use crate::config::{self, LineNumbers, SoftWrap};
+use crate::diff;
+use crate::encoding::Encoding;
+use crate::event::{Event, Events};
+use crate::file::{File, LineEnding};
+use crate::screen::{self, Cell, Position, Screen, Style};
+use crate::swap;
 
 /* This is the main editor source file for hecto! 
 This is a multiline comment to test the functionlity of syntax highlighting.
@@ -13,6 +22,18 @@ This is a multiline comment to test the functionlity of syntax highlighting.
 const HECTO_VERSION: &str = env!("CARGO_PKG_VERSION");
 const HECTOR_QUIT_TIMES: u8 = 3;
 const MESSAGE_TIMEOUT: Duration = std::time::Duration::from_secs(5);
+const SWAP_INTERVAL: Duration = std::time::Duration::from_secs(2);
+const SWAP_EDITS: u64 = 200;
+//longest the editor waits for input, the file is checked for changes on disk after each wait
+const DISK_CHECK_INTERVAL: Duration = std::time::Duration::from_secs(1);
+//line numbers in the gutter, from the same palette as the highlighting
+const GUTTER_COLOR: color::Rgb = color::Rgb(127, 140, 141);
+const GUTTER_CURRENT_COLOR: color::Rgb = color::Rgb(253, 188, 75);
+//backgrounds of the cursor's row and of the rulers
+const CURRENT_LINE_BG: color::Rgb = color::Rgb(49, 54, 59);
+const RULER_BG: color::Rgb = color::Rgb(77, 77, 77);
+//at the right edge of a wrapped row that goes on on the next line
+const WRAP_MARKER: &str = "\\";
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum SearchDirection {
@@ -36,6 +57,8 @@ impl StatusMessage {
 
 pub struct Editor {
     cursor: Position, //cursor Position
+    //in soft wrap mode nothing scrolls sideways, and `x` is the first visual line of row
+    //`y` on screen instead
     offset: Position,
     scr: Screen,
     file: File,
@@ -43,11 +66,27 @@ pub struct Editor {
     quit_times: u8,
     should_quit: bool,
     highlighted_word: Option<String>,
+    swap_enabled: bool,
+    swap_version: u64,
+    swap_time: Option<time::Instant>,
+    //swap file write running in the background, and the buffer version it writes
+    swap_job: Option<(u64, JoinHandle<()>)>,
+    events: Events,
+    line_numbers: LineNumbers,
+    soft_wrap: SoftWrap,
+    show_whitespace: bool,
+    highlight_current_line: bool,
+    rulers: Vec<usize>,
 }
 
-fn die(e: &dyn error::Error) {
-    Screen::clear();
-    panic!("{}", e);
+impl Drop for Editor {
+    //keep the unsaved changes around if we are going down for any reason other than the
+    //user quitting, including panics.
+    fn drop(&mut self) {
+        if !self.should_quit {
+            self.update_swap(true);
+        }
+    }
 }
 
 
@@ -55,63 +94,268 @@ impl Editor {
     pub fn new(file: File) -> Result<Self, std::io::Error> {
         
         let screen = Screen::default()?;
+        let line_numbers = file.line_numbers();
+        let soft_wrap = file.soft_wrap();
+        let show_whitespace = file.show_whitespace();
+        let highlight_current_line = file.highlight_current_line();
+        let rulers = file.rulers().to_vec();
         
         Ok(Self { 
             cursor: Default::default(), 
             offset: Default::default(),
             scr: screen, 
             file: file,
-            statusmsg: StatusMessage { text: String::from("HELP: Ctrl-S = save | Ctrl-Q = quit | Ctrl-F = search"), time: time::Instant::now()},
+            statusmsg: StatusMessage { text: String::from("HELP: Ctrl-S = save | Ctrl-Q = quit | Ctrl-F = search | Ctrl-G = goto | Ctrl-E = command"), time: time::Instant::now()},
             quit_times: 0,
             should_quit: false,
-            highlighted_word: None,})
-    }
-
-    fn draw_rows(&self) {
-        let height = self.scr.size().height;
-        for terminal_row in 0..height {
-            Screen::clear_current_line();
-            if let Some(row) = self.file.row(
-                self.offset.y.saturating_add(terminal_row as usize)) {
-                self.draw_row(row);
-            } else if self.file.is_empty() && terminal_row == height /3 {
-                self.draw_welcome_message();
+            highlighted_word: None,
+            swap_enabled: true,
+            swap_version: 0,
+            swap_time: None,
+            swap_job: None,
+            events: Events::new()?,
+            line_numbers,
+            soft_wrap,
+            show_whitespace,
+            highlight_current_line,
+            rulers,
+        })
+    }
+
+    fn draw_rows(&mut self) {
+        let height = self.scr.size().height as usize;
+        let mut lines = Vec::with_capacity(height);
+        let mut y = self.offset.y;
+        //with soft wrap the top row can be partly scrolled off
+        let mut first_line = if self.wraps() { self.offset.x } else { 0 };
+        while lines.len() < height {
+            let row = match self.file.row(y) {
+                Some(row) => row,
+                None => break,
+            };
+            if self.wraps() {
+                let starts = self.wrap_starts(y);
+                for line in first_line..starts.len() {
+                    lines.push(self.draw_wrapped_line(y, row, &starts, line));
+                }
+            } else {
+                lines.push(self.draw_row(y, row));
+            }
+            first_line = 0;
+            y += 1;
+        }
+        lines.truncate(height);
+        for terminal_row in lines.len()..height {
+            if self.file.is_empty() && terminal_row == height / 3 {
+                lines.push(self.draw_welcome_message());
             } else {
-                println!("~\r");
+                lines.push(screen::cells("~", Style::default()));
             }
         }
+        for (terminal_row, line) in lines.into_iter().enumerate() {
+            self.scr.draw_line(terminal_row, line);
+        }
     }
 
-    fn draw_row(&self, row: &Row){
-        let width = self.scr.size().width as usize;
+    fn draw_row(&self, y: usize, row: &Row) -> Vec<Cell> {
         let start = self.offset.x;
-        let end = self.offset.x.saturating_add(width);
-        let row = row.render(start, end);
-        println!("{}\r", row);
+        let end = self.offset.x.saturating_add(self.text_width());
+        let backgrounds = self.backgrounds(y);
+        let mut line = self.draw_gutter(y, self.gutter_width());
+        line.append(&mut row.render(start, end, self.file.tab_width(), self.show_whitespace, &backgrounds));
+        self.pad_backgrounds(&mut line, &backgrounds, start, self.scr.size().width as usize);
+        line
     }
 
-    fn draw_message_bar(&self) {
-        Screen::clear_current_line();
-        if time::Instant::now() - self.statusmsg.time <= MESSAGE_TIMEOUT {
-            let width = self.scr.size().width as usize;
-            let msg = if self.statusmsg.text.len() >= width /* single line multiline comment */ {
-                &self.statusmsg.text[..width]
-            } else {
-                &self.statusmsg.text[..]
+    ///Visual line `line` of row `y`, whose lines start at `starts`, with a marker at the
+    ///right edge when the row goes on.
+    fn draw_wrapped_line(&self, y: usize, row: &Row, starts: &[usize], line: usize) -> Vec<Cell> {
+        let gutter_width = self.gutter_width();
+        let mut cells = if line == 0 {
+            self.draw_gutter(y, gutter_width)
+        } else {
+            vec![Cell::default(); gutter_width]
+        };
+        let tab_width = self.file.tab_width();
+        let end = starts.get(line + 1).map_or(usize::MAX, |&end| row.column(end, tab_width));
+        let start = row.column(starts[line], tab_width);
+        let backgrounds = self.backgrounds(y);
+        cells.append(&mut row.render(start, end, tab_width, self.show_whitespace, &backgrounds));
+        let width = self.scr.size().width as usize;
+        if line + 1 < starts.len() {
+            let marker = gutter_width + self.text_width().saturating_sub(1);
+            self.pad_backgrounds(&mut cells, &backgrounds, start, marker);
+            cells.resize(marker, Cell::default());
+            let column = start + marker - gutter_width;
+            let style = Style {
+                fg: Some(GUTTER_COLOR),
+                bg: backgrounds.at(column..column + 1),
+                ..Style::default()
             };
-            print!("{}\r", msg);
+            cells.append(&mut screen::cells(WRAP_MARKER, style));
+        }
+        self.pad_backgrounds(&mut cells, &backgrounds, start, width);
+        cells
+    }
+
+    ///The background of the cursor's row and the rulers, for row `y`.
+    fn backgrounds(&self, y: usize) -> Backgrounds<'_> {
+        let current = self.highlight_current_line && y == self.cursor.y;
+        Backgrounds {
+            line: current.then_some(CURRENT_LINE_BG),
+            rulers: &self.rulers,
+            ruler: RULER_BG,
+        }
+    }
+
+    ///Carries `backgrounds` on past the end of the text in a line whose text starts at
+    ///column `start` of the row, up to terminal column `to`.
+    fn pad_backgrounds(&self, cells: &mut Vec<Cell>, backgrounds: &Backgrounds, start: usize, to: usize) {
+        let gutter_width = self.gutter_width();
+        let mut padding = Vec::new();
+        for x in cells.len().max(gutter_width)..to {
+            let column = start + x - gutter_width;
+            let style = Style { bg: backgrounds.at(column..column + 1), ..Style::default() };
+            padding.push(Cell { style, ..Cell::default() });
+        }
+        //blank cells at the end need not be drawn
+        while padding.last().is_some_and(|cell| cell.style.bg.is_none()) {
+            padding.pop();
+        }
+        if !padding.is_empty() {
+            cells.resize(cells.len().max(gutter_width), Cell::default());
+            cells.append(&mut padding);
+        }
+    }
+
+    ///Columns of the window left for the text of the rows.
+    fn text_width(&self) -> usize {
+        (self.scr.size().width as usize).saturating_sub(self.gutter_width())
+    }
+
+    ///Whether long rows are wrapped rather than scrolled sideways. Hex rows never are.
+    fn wraps(&self) -> bool {
+        self.soft_wrap != SoftWrap::Off && !self.file.is_hex()
+    }
+
+    ///Where the visual lines of row `y` start, leaving a column for the wrap marker. A
+    ///single line when not wrapping.
+    fn wrap_starts(&self, y: usize) -> Vec<usize> {
+        match self.file.row(y) {
+            Some(row) if self.wraps() => {
+                let words = self.soft_wrap == SoftWrap::Words;
+                row.wrap(self.text_width().saturating_sub(1), words, self.file.tab_width())
+            },
+            _ => vec![0],
+        }
+    }
 
+    ///The visual line right above line `line` of row `y`, which must not be the very first.
+    fn line_above(&self, (y, line): (usize, usize)) -> (usize, usize) {
+        if line > 0 {
+            (y, line - 1)
         } else {
-            //if this is the last line printed, 
-            //it should not generate a newline or else the screen printout will overflow
-            print!("\r"); 
+            (y - 1, self.wrap_starts(y - 1).len() - 1)
+        }
+    }
+
+    ///Moves from column `x` of row `y` to the same column of the visual line above or
+    ///below, in soft wrap mode.
+    fn move_visual(&self, x: usize, y: usize, down: bool) -> (usize, usize) {
+        let starts = self.wrap_starts(y);
+        let line = wrap_line(&starts, x);
+        let column = self.visual_column(y, &starts, x);
+        let (y, starts, line) = if down {
+            if line + 1 < starts.len() {
+                (y, starts, line + 1)
+            } else if y < self.file.len() {
+                (y + 1, self.wrap_starts(y + 1), 0)
+            } else {
+                return (x, y);
+            }
+        } else if line > 0 {
+            (y, starts, line - 1)
+        } else if y > 0 {
+            let starts = self.wrap_starts(y - 1);
+            let line = starts.len() - 1;
+            (y - 1, starts, line)
+        } else {
+            return (x, y);
+        };
+        //a line that is not the last ends right before the next one starts
+        let end = match starts.get(line + 1) {
+            Some(&next) => next - 1,
+            None => self.file.row_width(y),
+        };
+        let start = self.file.cursor_column(&Position { x: starts[line], y });
+        (self.file.cursor_at(y, start + column).clamp(starts[line], end), y)
+    }
+
+    ///Screen column of `x` on its visual line of row `y`, whose lines start at `starts`.
+    fn visual_column(&self, y: usize, starts: &[usize], x: usize) -> usize {
+        let start = starts[wrap_line(starts, x)];
+        let column = |x| self.file.cursor_column(&Position { x, y });
+        column(x) - column(start)
+    }
+
+    ///Wraps long rows at the width of the window as `mode` says, or switches to the next
+    ///mode when none is given.
+    fn set_soft_wrap(&mut self, mode: Option<&str>) {
+        let mode = match mode {
+            Some(name) => match SoftWrap::from_name(name) {
+                Some(mode) => mode,
+                None => {
+                    self.statusmsg = StatusMessage::from(format!(
+                        "Unknown soft wrap mode: {} (off, on, words)", name));
+                    return;
+                },
+            },
+            None => self.soft_wrap.next(),
+        };
+        self.soft_wrap = mode;
+        self.offset.x = 0;
+        self.scroll();
+        self.statusmsg = StatusMessage::from(format!("Soft wrap: {}", mode.to_str()));
+    }
+
+    ///Columns taken by the line numbers: enough digits for the last line, and a space.
+    fn gutter_width(&self) -> usize {
+        if self.line_numbers == LineNumbers::Off {
+            return 0;
+        }
+        self.file.num_rows().max(1).to_string().len() + 1
+    }
+
+    ///The number of row `y` in a gutter `width` columns wide.
+    fn draw_gutter(&self, y: usize, width: usize) -> Vec<Cell> {
+        let distance = y.abs_diff(self.cursor.y);
+        let number = match self.line_numbers {
+            LineNumbers::Off => return Vec::new(),
+            LineNumbers::Absolute => y + 1,
+            LineNumbers::Relative => distance,
+            LineNumbers::Hybrid if distance == 0 => y + 1,
+            LineNumbers::Hybrid => distance,
+        };
+        let fg = if distance == 0 { GUTTER_CURRENT_COLOR } else { GUTTER_COLOR };
+        let text = format!("{:>1$} ", number, width.saturating_sub(1));
+        screen::cells(&text, Style { fg: Some(fg), ..Style::default() })
+    }
+
+    fn draw_message_bar(&mut self) {
+        let y = self.scr.size().height as usize + 1;
+        if self.statusmsg.time.elapsed() < MESSAGE_TIMEOUT {
+            //the screen cuts the line at its width /* single line multiline comment */
+            let line = screen::cells(&self.statusmsg.text, Style::default());
+            self.scr.draw_line(y, line);
+        } else {
+            self.scr.draw_line(y, Vec::new());
         }
     }
 
     /* Another multiline comment
        found here. 
      */
-    fn draw_status_bar(&self) {
+    fn draw_status_bar(&mut self) {
         let filename = if let Some(name) = &self.file.filename {
             name
         } else {
@@ -119,71 +363,517 @@ impl Editor {
         };
 
         let modified = if self.file.dirty { "(modified)" } else { "" };
+        let large = if self.file.is_large() { "[large] " } else { "" };
+        let read_only = if self.file.is_read_only() { "[RO] " } else { "" };
         
         let mut status_msg = format!(
-            "{} - {} lines {}", 
-            filename, self.file.num_rows(), modified);
+            "{} - {} lines {}{}{}", 
+            filename, self.file.num_rows(), large, read_only, modified);
             
             
+            let format = if self.file.is_hex() {
+                "hex".to_string()
+            } else if self.file.is_listing() {
+                "dir".to_string()
+            } else {
+                format!("{} | {} | {}{}",
+                    if let Some(ft) = self.file.file_type() {
+                        ft.to_enum_str()
+                    } else {
+                        "no ft"
+                    },
+                    self.file.encoding().to_enum_str(),
+                    self.file.line_ending().to_enum_str(),
+                    if self.file.has_final_newline() { "" } else { " noeol" })
+            };
+            let format = match self.file.compression() {
+                Some(compression) => format!("{} | {}", format, compression.to_enum_str()),
+                None => format,
+            };
             let right_msg = format!(
                 "{} | {}/{} ",
-                if let Some(ft) = self.file.file_type() {
-                    ft.to_enum_str()
-                } else {
-                    "no ft"
-                },
+                format,
                 self.cursor.y,
                 self.file.num_rows(),
             );
             
             let width = self.scr.size().width as usize;
-            let padding =  width - right_msg.len() - status_msg.len();
+            let padding =  width.saturating_sub(screen::text_width(&right_msg) + screen::text_width(&status_msg));
             let spaces = " ".repeat(padding.saturating_sub(1));
             
             status_msg = format!("{}{}{}", status_msg, spaces, right_msg);
-            status_msg.truncate(width);
-        Screen::invert_colors();
-        println!("{}\r", status_msg);
-        Screen::reset_all_formatting();
+        let inverted = Style { inverted: true, ..Style::default() };
+        let y = self.scr.size().height as usize;
+        self.scr.draw_line(y, screen::cells(&status_msg, inverted));
     }
 
-    fn draw_welcome_message(&self){
-        let mut welcome_msg = format!("Hecto editor -- version {}\r", HECTO_VERSION);
+    fn draw_welcome_message(&self) -> Vec<Cell> {
+        let mut welcome_msg = format!("Hecto editor -- version {}", HECTO_VERSION);
         let width = self.scr.size().width as usize;
         let len = welcome_msg.len();
         let padding = width.saturating_sub(len)/2;
         let spaces = " ".repeat(padding.saturating_sub(1));
         welcome_msg = format!("~{}{}", spaces, welcome_msg);
-        welcome_msg.truncate(width);
-        println!("{}\r", welcome_msg);
+        screen::cells(&welcome_msg, Style::default())
     }
 
-    fn save(&mut self){
+    ///Writes the buffer to its file. Returns whether it was saved.
+    fn save(&mut self) -> bool {
         if self.file.filename.is_none() {
-            let new_name = self.prompt(
-                "Save as: ", 
-                |_, _, _|{}).unwrap_or(None);
-            if new_name.is_none() {
+            return self.save_as(None);
+        }
+
+        if self.file.changed_on_disk() {
+            let choice = self.prompt_choice(
+                "File changed on disk since it was read: (o)verwrite, ESC = cancel", "o")
+                .unwrap_or(None);
+            if choice.is_none() {
                 self.statusmsg = StatusMessage::from("Save aborted.".to_string());
+                return false;
+            }
+        }
+
+        if !self.load_whole_file() {
+            return false;
+        }
+        self.cursor = self.file.clean_up(&self.cursor);
+        match self.file.save() {
+            Ok(n) => {
+                self.statusmsg = StatusMessage::from(format!("{} bytes written to disk", n));
+                if self.swap_enabled {
+                    self.remove_swap();
+                }
+                true
+            },
+            Err(e) => {
+                self.statusmsg = StatusMessage::from(format!("Error writing to file: {}", e));
+                false
+            }
+        }
+    }
+
+    ///Saves the buffer under a new name, which the buffer then keeps if the save worked.
+    ///Returns whether it was saved.
+    fn save_as(&mut self, filename: Option<&str>) -> bool {
+        let filename = match self.ask("Save as: ", filename) {
+            //saving the buffer to its own file is just a save
+            Some(filename) if self.file.filename.as_deref() == Some(&filename[..])
+                || self.confirm_overwrite(&filename) => filename,
+            _ => {
+                self.statusmsg = StatusMessage::from("Save aborted.".to_string());
+                return false;
+            }
+        };
+        let old_swap = self.file.swap_path();
+        let old = self.file.set_filename(&filename);
+        if !self.save() {
+            self.file.restore_filename(old);
+            return false;
+        }
+        //the unsaved changes kept for the old name are saved now
+        if let Some(path) = old_swap.filter(|_| self.swap_enabled) {
+            self.wait_for_swap();
+            swap::remove(&path);
+        }
+        true
+    }
+
+    ///Moves the current file to a new name on disk.
+    fn rename(&mut self, filename: Option<&str>) {
+        if self.file.filename.is_none() {
+            self.statusmsg = StatusMessage::from(
+                "Buffer has no file to rename, use saveas instead.".to_string());
+            return;
+        }
+        let filename = match self.ask("Rename to: ", filename) {
+            Some(filename) if self.confirm_overwrite(&filename) => filename,
+            _ => {
+                self.statusmsg = StatusMessage::from("Rename aborted.".to_string());
+                return;
+            }
+        };
+        if self.swap_enabled {
+            self.remove_swap();
+        }
+        match self.file.rename(&filename) {
+            Ok(()) => {
+                self.statusmsg = StatusMessage::from(format!("Renamed to {}", filename));
+            },
+            Err(e) => {
+                self.statusmsg = StatusMessage::from(format!("Could not rename: {}", e));
+            }
+        }
+    }
+
+    ///Writes a range of lines, given as `10-20` or `10`, to another file. `args` may hold
+    ///the range followed by the file name, anything missing is prompted for.
+    fn write_range(&mut self, args: Option<&str>) {
+        let (range, filename) = match args.map(|args| args.split_once(' ')) {
+            Some(Some((range, filename))) => (Some(range), Some(filename.trim())),
+            Some(None) => (args, None),
+            None => (None, None),
+        };
+        let range = self.ask("Lines to write (e.g. 10-20): ", range);
+        let range = range.as_deref().and_then(|range| {
+            let (start, end) = range.split_once('-').unwrap_or((range, range));
+            let start = start.trim().parse::<usize>().ok()?;
+            let end = end.trim().parse::<usize>().ok()?;
+            (start > 0 && start <= end).then_some((start - 1, end))
+        });
+        let (start, end) = if let Some(range) = range {
+            range
+        } else {
+            self.statusmsg = StatusMessage::from("Not a line range.".to_string());
+            return;
+        };
+        let filename = match self.ask("Write lines to: ", filename) {
+            Some(filename) if self.confirm_overwrite(&filename) => filename,
+            _ => {
+                self.statusmsg = StatusMessage::from("Write aborted.".to_string());
+                return;
+            }
+        };
+        match self.file.write_range(start, end, &filename) {
+            Ok(n) => {
+                self.statusmsg = StatusMessage::from(format!("{} bytes written to {}", n, filename));
+            },
+            Err(e) => {
+                self.statusmsg = StatusMessage::from(format!("Error writing to file: {}", e));
+            }
+        }
+    }
+
+    ///Returns `given` if set, and prompts for the value otherwise.
+    fn ask(&mut self, prompt: &str, given: Option<&str>) -> Option<String> {
+        match given {
+            Some(value) => Some(value.to_string()),
+            None => self.prompt(prompt, |_, _, _| {}).unwrap_or(None),
+        }
+    }
+
+    ///Asks before replacing an existing file.
+    fn confirm_overwrite(&mut self, filename: &str) -> bool {
+        if !Path::new(filename).exists() {
+            return true;
+        }
+        let choice = self.prompt_choice(
+            &format!("{} already exists, overwrite it? (y/n)", filename), "y");
+        matches!(choice, Ok(Some('y')))
+    }
+
+    ///Prompts for a named command and runs it. Commands that don't have a key binding of
+    ///their own live here.
+    fn command(&mut self) {
+        let command = self.prompt("Command: ", |_, _, _| {}).unwrap_or(None);
+        if let Some(command) = command {
+            self.execute_command(&command);
+        }
+    }
+
+    fn execute_command(&mut self, command: &str) {
+        //everything after the command name is its argument, so file names may have spaces
+        let (name, arg) = match command.trim().split_once(' ') {
+            Some((name, arg)) => (name, Some(arg.trim())),
+            None => (command.trim(), None),
+        };
+        match (name, arg) {
+            ("eol" | "encoding", Some(_)) if !self.can_edit() => {},
+            ("reopen" | "backups" | "readonly" | "saveas" | "rename", _) if self.file.is_listing() => {
+                self.statusmsg = StatusMessage::from(
+                    format!("{} is not available in a directory listing.", name));
+            },
+            ("eol", Some(style)) => {
+                if let Some(line_ending) = LineEnding::from_name(style) {
+                    self.file.set_line_ending(line_ending);
+                    self.statusmsg = StatusMessage::from(
+                        format!("Line endings set to {}", line_ending.to_enum_str()));
+                } else {
+                    self.statusmsg = StatusMessage::from(
+                        format!("Unknown line ending: {} (use lf or crlf)", style));
+                }
+            },
+            ("encoding", Some(name)) => {
+                if let Some(encoding) = Encoding::from_name(name) {
+                    self.file.set_encoding(encoding);
+                    self.statusmsg = StatusMessage::from(
+                        format!("File will be saved as {}", encoding.to_enum_str()));
+                } else {
+                    self.statusmsg = StatusMessage::from(format!("Unknown encoding: {}", name));
+                }
+            },
+            ("reopen", Some(name)) => {
+                if let Some(encoding) = Encoding::from_name(name) {
+                    self.reopen(encoding);
+                } else {
+                    self.statusmsg = StatusMessage::from(format!("Unknown encoding: {}", name));
+                }
+            },
+            ("backups", None) => {
+                if self.can_edit() {
+                    self.restore_backup();
+                }
+            },
+            ("readonly", None) => {
+                let read_only = !self.file.is_read_only();
+                self.file.set_read_only(read_only);
+                self.statusmsg = StatusMessage::from(format!(
+                    "Buffer is now {}.", if read_only { "read-only" } else { "writable" }));
+            },
+            ("goto", Some(line)) => self.goto(line),
+            ("saveas", filename) => {
+                self.save_as(filename);
+            },
+            ("rename", filename) => self.rename(filename),
+            ("write", args) => self.write_range(args),
+            ("dir", None) => self.open_parent(),
+            ("newfile", name) => self.create_entry(name, false),
+            ("mkdir", name) => self.create_entry(name, true),
+            ("numbers", mode) => self.set_line_numbers(mode),
+            ("wrap", mode) => self.set_soft_wrap(mode),
+            ("cursorline", None) => {
+                self.highlight_current_line = !self.highlight_current_line;
+                self.statusmsg = StatusMessage::from(format!(
+                    "Current line highlight {}.", if self.highlight_current_line { "on" } else { "off" }));
+            },
+            ("rulers", columns) => {
+                self.rulers = columns.map(config::parse_columns).unwrap_or_default();
+                self.statusmsg = StatusMessage::from(if self.rulers.is_empty() {
+                    "Rulers off.".to_string()
+                } else {
+                    let columns: Vec<String> = self.rulers.iter().map(usize::to_string).collect();
+                    format!("Rulers at columns {}.", columns.join(", "))
+                });
+            },
+            ("whitespace", None) => {
+                self.show_whitespace = !self.show_whitespace;
+                self.statusmsg = StatusMessage::from(format!(
+                    "Visible whitespace {}.", if self.show_whitespace { "on" } else { "off" }));
+            },
+            _ => {
+                self.statusmsg = StatusMessage::from(format!("Unknown command: {}", command));
+            }
+        }
+    }
+
+    ///Switches the line number gutter to `mode`, or to the next mode when none is given.
+    fn set_line_numbers(&mut self, mode: Option<&str>) {
+        let mode = match mode {
+            Some(name) => match LineNumbers::from_name(name) {
+                Some(mode) => mode,
+                None => {
+                    self.statusmsg = StatusMessage::from(format!(
+                        "Unknown line numbers mode: {} (off, absolute, relative, hybrid)", name));
+                    return;
+                },
+            },
+            None => self.line_numbers.next(),
+        };
+        self.line_numbers = mode;
+        //the gutter takes its columns from the text
+        self.scroll();
+        self.statusmsg = StatusMessage::from(format!("Line numbers: {}", mode.to_str()));
+    }
+
+    ///Replaces the buffer with the file or directory at `path`.
+    fn open(&mut self, path: &Path) {
+        if self.file.dirty {
+            self.statusmsg = StatusMessage::from(
+                "File has unsaved changes, save it before opening another.".to_string());
+            return;
+        }
+        match File::open(&path.to_string_lossy()) {
+            Ok(mut file) => {
+                //opened with --readonly, the whole session is
+                if self.file.is_forced_read_only() {
+                    file.set_read_only(true);
+                }
+                self.file = file;
+                self.cursor = Position::default();
+                self.offset = Position::default();
+                self.highlighted_word = None;
+                self.swap_enabled = true;
+                self.swap_version = self.file.version();
+                self.swap_time = None;
+                if let Err(e) = self.check_swap() {
+                    self.statusmsg = StatusMessage::from(format!("Could not check swap file: {}", e));
+                }
+            },
+            Err(e) => {
+                self.statusmsg = StatusMessage::from(
+                    format!("Could not open {}: {}", path.display(), e));
+            }
+        }
+    }
+
+    ///Opens the directory holding the current file or directory, with the cursor on the
+    ///entry we came from.
+    fn open_parent(&mut self) {
+        let current = self.file.filename.as_ref().and_then(|name| fs::canonicalize(name).ok());
+        let parent = match &current {
+            Some(path) => match path.parent() {
+                Some(parent) => parent.to_path_buf(),
+                None => {
+                    self.statusmsg = StatusMessage::from("Already at the root directory.".to_string());
+                    return;
+                }
+            },
+            None => PathBuf::from("."),
+        };
+        self.open(&parent);
+        if let Some(y) = current.and_then(|path| self.file.listing_row(&path)) {
+            self.cursor.y = y;
+        }
+    }
+
+    ///Opens the entry under the cursor in a directory listing.
+    fn open_entry(&mut self) {
+        let entry = match self.file.listing_entry(self.cursor.y) {
+            Some(entry) => entry.to_path_buf(),
+            None => return,
+        };
+        let dir = self.file.filename.as_deref().map(Path::new);
+        if dir.and_then(Path::parent) == Some(&entry) {
+            self.open_parent();
+        } else {
+            self.open(&entry);
+        }
+    }
+
+    ///Creates a new file, which is then opened, or a new directory, which is then shown in
+    ///the listing. Relative names are taken from the directory being listed.
+    fn create_entry(&mut self, name: Option<&str>, is_dir: bool) {
+        let prompt = if is_dir { "New directory: " } else { "New file: " };
+        let name = match self.ask(prompt, name) {
+            Some(name) if !name.is_empty() => name,
+            _ => {
+                self.statusmsg = StatusMessage::from("Aborted.".to_string());
                 return;
             }
-            self.file.filename = new_name;
+        };
+        let path = match &self.file.filename {
+            Some(dir) if self.file.is_listing() => Path::new(dir).join(&name),
+            _ => PathBuf::from(&name),
+        };
+        let result = if is_dir {
+            fs::create_dir(&path)
+        } else {
+            fs::File::create_new(&path).map(|_| ())
+        };
+        if let Err(e) = result {
+            self.statusmsg = StatusMessage::from(format!("Could not create {}: {}", name, e));
+            return;
+        }
+        if !is_dir {
+            self.open(&path);
+        } else if self.file.is_listing() {
+            //show the new directory right away rather than when the disk check notices it
+            match self.file.reload() {
+                Ok(()) => {
+                    self.cursor.y = self.file.listing_row(&path).unwrap_or(self.cursor.y);
+                    self.clamp_cursor();
+                    self.statusmsg = StatusMessage::from(format!("Created {}", name));
+                },
+                Err(e) => {
+                    self.statusmsg = StatusMessage::from(format!("Could not reload: {}", e));
+                }
+            }
+        } else {
+            self.statusmsg = StatusMessage::from(format!("Created {}", name));
+        }
+    }
+
+    ///Reads the file from disk again, decoding it with `encoding`.
+    fn reopen(&mut self, encoding: Encoding) {
+        let filename = if let Some(name) = &self.file.filename {
+            name.clone()
+        } else {
+            self.statusmsg = StatusMessage::from("Buffer has no file to reopen.".to_string());
+            return;
+        };
+        if self.file.dirty {
+            self.statusmsg = StatusMessage::from(
+                "File has unsaved changes, save it before reopening.".to_string());
+            return;
+        }
+        match File::open_with_encoding(&filename, Some(encoding)) {
+            Ok(file) => {
+                self.file = file;
+                self.clamp_cursor();
+                self.statusmsg = StatusMessage::from(
+                    format!("Reopened as {}", encoding.to_enum_str()));
+            },
+            Err(e) => {
+                self.statusmsg = StatusMessage::from(
+                    format!("Could not reopen as {}: {}", encoding.to_enum_str(), e));
+            }
         }
+    }
 
-        if let Ok(n) = self.file.save() {
-            self.statusmsg = StatusMessage::from(format!("{} bytes written to disk", n));
+    ///Lists the backups of the current file and loads the chosen one into the buffer.
+    fn restore_backup(&mut self) {
+        let backups = self.file.backups();
+        if backups.is_empty() {
+            self.statusmsg = StatusMessage::from("No backups found.".to_string());
+            return;
+        }
+        let labels: Vec<String> = backups.iter().enumerate().map(|(index, path)| {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            //numbered backups carry the whole encoded path, only the number is interesting
+            let label = match name.rsplit_once(".~") {
+                Some((_, number)) => format!("~{}", number),
+                None => name.to_string(),
+            };
+            format!("[{}] {}", index + 1, label)
+        }).collect();
+        let choice = self.prompt(
+            &format!("{} - restore #: ", labels.join(" ")),
+            |_, _, _| {}).unwrap_or(None);
+        let backup = choice
+            .and_then(|choice| choice.trim().parse::<usize>().ok())
+            .and_then(|number| number.checked_sub(1))
+            .and_then(|index| backups.get(index));
+        let backup = if let Some(backup) = backup {
+            backup
         } else {
-            self.statusmsg = StatusMessage::from("Error writing to file.".to_string());
+            self.statusmsg = StatusMessage::from("Restore aborted.".to_string());
+            return;
+        };
+        match File::open_with_encoding(&backup.to_string_lossy(), Some(self.file.encoding())) {
+            Ok(file) => {
+                self.file.replace_contents(file);
+                self.clamp_cursor();
+                self.statusmsg = StatusMessage::from(
+                    format!("Restored {}", backup.display()));
+            },
+            Err(e) => {
+                self.statusmsg = StatusMessage::from(format!("Could not restore backup: {}", e));
+            }
         }
+    }
 
+    ///Moves the cursor back inside the file after its contents were replaced.
+    fn clamp_cursor(&mut self) {
+        if self.cursor.y > self.file.len() {
+            self.cursor.y = self.file.len();
+        }
+        let width = self.file.row_width(self.cursor.y);
+        if self.cursor.x > width {
+            self.cursor.x = width;
+        }
+        self.scroll();
     }
 
     fn search(&mut self){
         let saved_position = self.cursor.clone();
         let saved_offset = self.offset.clone();
         let mut direction = SearchDirection::Forward;
+        let prompt = if self.file.is_hex() {
+            "Search hex bytes (Use ESC/Arrows/Enter): "
+        } else {
+            "Search (Use ESC/Arrows/Enter): "
+        };
         let query = self
-            .prompt("Search (Use ESC/Arrows/Enter): ", 
+            .prompt(prompt, 
                 |editor, key, query|{
                     let mut moved = false;
                     match key {
@@ -217,18 +907,231 @@ impl Editor {
     }
 
 
-    pub fn run(&mut self){
+    pub fn run(&mut self) -> Result<(), std::io::Error> {
+        if let Err(e) = self.check_swap() {
+            return Err(self.die(e));
+        }
         loop {
+            if let Err(e) = self.check_disk() {
+                return Err(self.die(e));
+            }
             if let Err(e) = self.refresh_screen() {
-                die(&e);
+                return Err(self.die(e));
             }
             if self.should_quit {
+                if self.swap_enabled {
+                    self.remove_swap();
+                }
                 break;
             }
-            if let Err(e) = self.process_keypress() {
-                die(&e);
+            let result = match self.next_key() {
+                Ok(Some(key)) => self.process_keypress(key),
+                Ok(None) => Ok(()),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                return Err(self.die(e));
             }
-            
+            self.update_swap(false);
+        }
+        Ok(())
+    }
+
+    ///Saves what can be saved after an unrecoverable error, and hands the error back so it
+    ///can be reported once the terminal is restored.
+    fn die(&mut self, e: std::io::Error) -> std::io::Error {
+        self.update_swap(true);
+        let _ = self.scr.clear();
+        e
+    }
+
+    ///Writes the buffer to its swap file if it changed since the last write, and either
+    ///`force` is set or enough time or edits have gone by.
+    fn update_swap(&mut self, force: bool) {
+        let version = self.file.version();
+        if !self.swap_enabled || !self.file.dirty || version == self.swap_version {
+            return;
+        }
+        let edits = version.wrapping_sub(self.swap_version);
+        let recent = self.swap_time.is_some_and(|t| t.elapsed() < SWAP_INTERVAL);
+        if !force && (recent && edits < SWAP_EDITS || self.swap_job.is_some()) {
+            return;
+        }
+        let (path, text) = match self.file.swap_snapshot() {
+            Some(snapshot) => snapshot,
+            None => return,
+        };
+        self.wait_for_swap();
+        //a failed write is retried on the next interval
+        self.swap_time = Some(time::Instant::now());
+        if force {
+            if swap::write(&path, &text).is_ok() {
+                self.swap_version = version;
+            }
+            return;
+        }
+        let job = self.events.spawn(move || swap::write(&path, &text), move |editor: &mut Editor, result| {
+            //a job that was waited for already has been replaced, its result is old news
+            if editor.swap_job.as_ref().is_none_or(|(job_version, _)| *job_version != version) {
+                return;
+            }
+            //the job sends this just before its thread ends, so the join doesn't block
+            editor.wait_for_swap();
+            if result.is_ok() {
+                editor.swap_version = version;
+            }
+        });
+        self.swap_job = Some((version, job));
+    }
+
+    ///Lets a swap file write running in the background finish, so that it cannot land after
+    ///whatever is done to the swap file next.
+    fn wait_for_swap(&mut self) {
+        if let Some((_, job)) = self.swap_job.take() {
+            let _ = job.join();
+        }
+    }
+
+    fn remove_swap(&mut self) {
+        self.wait_for_swap();
+        self.file.remove_swap();
+    }
+
+    ///Looks for a swap file left over from an earlier session, and lets the user recover
+    ///it, compare it with the file on disk, or throw it away.
+    fn check_swap(&mut self) -> Result<(), std::io::Error> {
+        let swap = match self.file.read_swap() {
+            Ok(Some(swap)) => swap,
+            _ => return Ok(()),
+        };
+        if swap.is_live() {
+            //the other process owns the swap file, writing ours would clobber its
+            self.swap_enabled = false;
+            self.statusmsg = StatusMessage::from(format!(
+                "Warning! File is being edited by another hecto (pid {}).", swap.pid));
+            return Ok(());
+        }
+        loop {
+            let choice = self.prompt_choice(
+                "Swap file found: (r)ecover, (d)iff, (x) discard, ESC = leave it alone",
+                "rdx")?;
+            match choice {
+                Some('r') => {
+                    self.file.replace_contents(File::from_text(&swap.text));
+                    self.clamp_cursor();
+                    self.statusmsg = StatusMessage::from(
+                        "Recovered unsaved changes from swap file.".to_string());
+                },
+                Some('d') => {
+                    let disk = self.file.contents();
+                    let disk: Vec<&str> = disk.lines().collect();
+                    let recovered: Vec<&str> = swap.text.lines().collect();
+                    let lines = diff::diff(&disk, &recovered);
+                    let message = if lines.is_empty() {
+                        "Swap file matches the file on disk, press any key"
+                    } else {
+                        "Swap file vs disk, press any key"
+                    };
+                    self.show_lines(&lines, message)?;
+                    continue;
+                },
+                Some('x') => {
+                    self.remove_swap();
+                    self.statusmsg = StatusMessage::from("Swap file discarded.".to_string());
+                },
+                _ => {
+                    self.swap_enabled = false;
+                    self.statusmsg = StatusMessage::from(
+                        "Swap file left alone, changes to this buffer won't be swapped.".to_string());
+                },
+            }
+            return Ok(());
+        }
+    }
+
+    ///Notices when another program changed the file. Untouched buffers are reloaded right
+    ///away, otherwise the user decides which version wins.
+    fn check_disk(&mut self) -> Result<(), std::io::Error> {
+        if !self.file.changed_on_disk() {
+            return Ok(());
+        }
+        if !self.file.dirty {
+            return match self.file.reload() {
+                Ok(()) => {
+                    self.clamp_cursor();
+                    self.statusmsg = StatusMessage::from("File changed on disk, reloaded.".to_string());
+                    Ok(())
+                },
+                Err(e) => {
+                    //most likely deleted or being rewritten, leave the buffer as it is
+                    self.file.acknowledge_disk_change();
+                    self.statusmsg = StatusMessage::from(format!("File changed on disk: {}", e));
+                    Ok(())
+                },
+            };
+        }
+        loop {
+            let choice = self.prompt_choice(
+                "File changed on disk: (r)eload, (o)verwrite, (d)iff, ESC = keep editing",
+                "rod")?;
+            match choice {
+                Some('r') => {
+                    if let Err(e) = self.file.reload() {
+                        self.statusmsg = StatusMessage::from(format!("Could not reload: {}", e));
+                    } else {
+                        self.clamp_cursor();
+                        self.statusmsg = StatusMessage::from("Reloaded from disk.".to_string());
+                    }
+                },
+                Some('o') => {
+                    self.file.acknowledge_disk_change();
+                    self.save();
+                },
+                Some('d') => {
+                    let disk = self.file.disk_contents().unwrap_or_default();
+                    let disk: Vec<&str> = disk.lines().collect();
+                    let buffer = self.file.contents();
+                    let buffer: Vec<&str> = buffer.lines().collect();
+                    let lines = diff::diff(&disk, &buffer);
+                    self.show_lines(&lines, "Disk vs buffer, press any key")?;
+                    continue;
+                },
+                _ => {
+                    self.file.acknowledge_disk_change();
+                    self.statusmsg = StatusMessage::from(
+                        "Warning! Buffer differs from the file on disk.".to_string());
+                },
+            }
+            return Ok(());
+        }
+    }
+
+    ///Temporarily shows `lines` in place of the buffer until a key is pressed.
+    fn show_lines(&mut self, lines: &[String], message: &str) -> Result<(), std::io::Error> {
+        let file = std::mem::replace(&mut self.file, File::from_text(&lines.join("\n")));
+        let cursor = std::mem::take(&mut self.cursor);
+        let offset = std::mem::take(&mut self.offset);
+        let result = self.prompt_choice(message, "");
+        self.file = file;
+        self.cursor = cursor;
+        self.offset = offset;
+        result.map(|_| ())
+    }
+
+    ///Shows `prompt` in the message bar and waits for one of the keys in `choices`.
+    ///Any other key cancels the prompt.
+    fn prompt_choice(&mut self, prompt: &str, choices: &str) -> Result<Option<char>, std::io::Error> {
+        let key = loop {
+            self.statusmsg = StatusMessage::from(prompt.to_string());
+            self.refresh_screen()?;
+            if let Some(key) = self.next_key()? {
+                break key;
+            }
+        };
+        self.statusmsg = StatusMessage::from(String::new());
+        match key {
+            Key::Char(c) if choices.contains(c) => Ok(Some(c)),
+            _ => Ok(None),
         }
     }
     fn scroll(&mut self) {
@@ -238,7 +1141,23 @@ impl Editor {
         // }
 
         let height = self.scr.size().height as usize;
-        let width = self.scr.size().width as usize;
+        let width = self.text_width();
+
+        if self.wraps() {
+            //the same as below, but counting visual lines
+            let cursor = (self.cursor.y, wrap_line(&self.wrap_starts(self.cursor.y), self.cursor.x));
+            let top = (self.offset.y, self.offset.x);
+            let mut line = cursor;
+            let mut above = 0;
+            while line > top && above + 1 < height {
+                line = self.line_above(line);
+                above += 1;
+            }
+            if cursor < top || line > top {
+                (self.offset.y, self.offset.x) = if cursor < top { cursor } else { line };
+            }
+            return;
+        }
 
         //update offsets based on cursor position.
         //if the offset if past the cursor position, scroll up so the cursor occupies the top line.
@@ -250,23 +1169,88 @@ impl Editor {
             self.offset.y = self.cursor.y - height + 1;
         }
 
-        if self.cursor.x < self.offset.x {
-            self.offset.x = self.cursor.x;
+        let column = self.file.cursor_column(&self.cursor);
+        if column < self.offset.x {
+            self.offset.x = column;
+        }
+        if column >= self.offset.x + width {
+            self.offset.x = column - width + 1;
         }
-        if self.cursor.x >= self.offset.x + width {
-            self.offset.x = self.cursor.x - width + 1;
+    }
+
+    ///Waits for the next key, applying the results of background jobs and running the
+    ///timers in the meantime. Returns None when something else woke us up, so that the
+    ///caller can redraw.
+    fn next_key(&mut self) -> Result<Option<Key>, std::io::Error> {
+        match self.events.next(self.next_timer()) {
+            Some(Event::Key(key)) => Ok(Some(key)),
+            Some(Event::InputError(e)) => Err(e),
+            Some(Event::Signal(number)) => Err(Error::new(
+                ErrorKind::Interrupted, format!("terminated by signal {}", number))),
+            Some(Event::Resize) => {
+                self.scr.resize()?;
+                //keeps the cursor on screen
+                self.clamp_cursor();
+                Ok(None)
+            },
+            Some(Event::Job(done)) => {
+                done(self);
+                Ok(None)
+            },
+            None => {
+                self.update_swap(false);
+                Ok(None)
+            },
         }
     }
 
-    fn process_keypress(&mut self) -> Result<(), std::io::Error> {
-        let key = Screen::read_key()?;
+    ///Time until the next timer is due: the status message expiring, the swap file being
+    ///written, or the file being checked for changes on disk.
+    fn next_timer(&self) -> Duration {
+        let now = time::Instant::now();
+        let mut due = now + DISK_CHECK_INTERVAL;
+        let expires = self.statusmsg.time + MESSAGE_TIMEOUT;
+        if expires > now {
+            due = due.min(expires);
+        }
+        let unsaved = self.swap_enabled && self.file.dirty && self.file.version() != self.swap_version;
+        if unsaved && self.swap_job.is_none() {
+            due = due.min(self.swap_time.map_or(now, |t| t + SWAP_INTERVAL));
+        }
+        due.saturating_duration_since(now)
+    }
 
+    fn process_keypress(&mut self, key: Key) -> Result<(), std::io::Error> {
         match key {
+            Key::Char('\n') if self.file.is_listing() => self.open_entry(),
+            Key::Char('-') if self.file.is_listing() => self.open_parent(),
+            Key::Char(_) | Key::Backspace | Key::Delete if !self.can_edit() => {},
+            Key::Char(c) if self.file.is_hex() => {
+                if c.is_ascii_hexdigit() {
+                    self.file.insert(&self.cursor, c);
+                    self.move_cursor(Key::Right);
+                } else {
+                    self.statusmsg = StatusMessage::from(
+                        "Type hex digits to overwrite the bytes under the cursor.".to_string());
+                }
+            },
+            Key::Backspace | Key::Delete if self.file.is_hex() => {
+                self.statusmsg = StatusMessage::from(
+                    "Hex mode is overwrite-only.".to_string());
+            },
+            Key::Char('\t') if self.file.soft_tab().is_some() => {
+                let width = self.file.soft_tab().unwrap_or(1);
+                let column = self.file.cursor_column(&self.cursor);
+                for _ in 0..width - column % width {
+                    self.file.insert(&self.cursor, ' ');
+                    self.move_cursor(Key::Right);
+                }
+            },
             Key::Char(c) => {
                 self.file.insert(&self.cursor, c);
                 self.move_cursor(Key::Right);
                 if c == '\n' {
-                    let count = self.file.row(self.cursor.y).unwrap().get_prefix_len(" ");
+                    let count = self.file.row(self.cursor.y).unwrap().indent().len();
                     for _ in 0..count {
                         self.move_cursor(Key::Right);
                     }
@@ -289,6 +1273,15 @@ impl Editor {
             Key::Ctrl('f') => {
                 self.search();
             },
+            Key::Ctrl('e') => {
+                self.command();
+            },
+            Key::Ctrl('g') => {
+                let line = self.prompt("Go to line: ", |_, _, _| {}).unwrap_or(None);
+                if let Some(line) = line {
+                    self.goto(&line);
+                }
+            },
             Key::Ctrl('h') => {},
             Key::Backspace => {
                 if self.cursor.x > 0 || self.cursor.y > 0 {
@@ -314,15 +1307,70 @@ impl Editor {
         Ok(())
     }
 
+    ///Whether the buffer may be changed, telling the user why not otherwise.
+    fn can_edit(&mut self) -> bool {
+        if self.file.is_listing() {
+            self.statusmsg = StatusMessage::from(
+                "Directory listings can't be edited, Enter opens an entry and - goes up.".to_string());
+            return false;
+        }
+        if self.file.is_read_only() {
+            self.statusmsg = StatusMessage::from(
+                "Buffer is read-only, use the readonly command to allow changes.".to_string());
+            return false;
+        }
+        self.load_whole_file()
+    }
+
+    ///Reads all of a large file into memory, which it has to be in to be changed or saved,
+    ///once the user agrees to it. Returns whether the whole file is loaded.
+    fn load_whole_file(&mut self) -> bool {
+        if !self.file.is_large() {
+            return true;
+        }
+        let choice = self.prompt_choice(
+            "Changing a large file reads all of it into memory: (l)oad it, ESC = cancel", "l")
+            .unwrap_or(None);
+        if choice.is_none() {
+            self.statusmsg = StatusMessage::from("Large file left unchanged.".to_string());
+            return false;
+        }
+        match self.file.load_all() {
+            Ok(()) => {
+                self.statusmsg = StatusMessage::from(
+                    format!("Loaded all {} lines of the file.", self.file.len()));
+                true
+            },
+            Err(e) => {
+                self.statusmsg = StatusMessage::from(format!("Can't load the whole file: {}", e));
+                false
+            },
+        }
+    }
+
+    ///Moves the cursor to the start of the 1-based line number `line`.
+    fn goto(&mut self, line: &str) {
+        match line.trim().parse::<usize>() {
+            Ok(line) if line > 0 => {
+                self.cursor = Position { x: 0, y: (line - 1).min(self.file.len()) };
+                self.scroll();
+            },
+            _ => {
+                self.statusmsg = StatusMessage::from(format!("Not a line number: {}", line));
+            }
+        }
+    }
+
     fn move_cursor(&mut self, key: Key){
         let Position { mut x, mut y} = self.cursor;
         let height = self.file.len();
         let terminal_height = self.scr.size().height as usize;
-        let width = if let Some(row) = self.file.row(y) {
-            row.len()
-        } else {
-            0
-        };
+        //in large file mode, rows a page up or down may not be loaded yet
+        if let Err(e) = self.file.load_rows(y.saturating_sub(terminal_height),
+            y.saturating_add(terminal_height).saturating_add(1)) {
+            self.statusmsg = StatusMessage::from(format!("Error reading the file: {}", e));
+        }
+        let width = self.file.row_width(y);
 
         match key {
             //TODO: handle errors properly, and avoid panicking
@@ -331,11 +1379,7 @@ impl Editor {
                     x -= 1;
                 } else if y > 0 {
                     y -= 1;
-                    if let Some(row) = self.file.row(y) {
-                        x = row.len();
-                    } else {
-                        x = 0;
-                    }
+                    x = self.file.row_width(y);
                 }
             },
             Key::Right => {
@@ -346,22 +1390,17 @@ impl Editor {
                     y += 1;
                 }
             }
+            Key::Up if self.wraps() => (x, y) = self.move_visual(x, y, false),
+            Key::Down if self.wraps() => (x, y) = self.move_visual(x, y, true),
+            //keep to the same screen column, which differs from x when there are tabs
             Key::Up => { 
                 y = y.saturating_sub(1);
-                if let Some(row) = self.file.row(y) {
-                    if x > row.len() {
-                        x = row.len()
-                    }
-                }
+                x = self.file.cursor_at(y, self.file.cursor_column(&self.cursor));
             },
             Key::Down => {
                 if y < height {
                     y = y.saturating_add(1);
-                    if let Some(row) = self.file.row(y) {
-                        if x > row.len() {
-                            x = row.len()
-                        }
-                    }
+                    x = self.file.cursor_at(y, self.file.cursor_column(&self.cursor));
                 }
             },
             Key::Home => x = 0,
@@ -379,27 +1418,38 @@ impl Editor {
             _ => {}
         }
 
-        self.cursor = Position {x, y}
+        self.cursor = Position {x: x.min(self.file.row_width(y)), y}
     }
 
     fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
-        Screen::cursor_hide();
-        Screen::cursor_position(&Position::default());
         if self.should_quit {
-            Screen::clear();
+            self.scr.clear()
         } else {
-            self.file.highlight(&self.highlighted_word, 
+            let height = self.scr.size().height as usize;
+            self.file.load_rows(self.offset.y, self.offset.y.saturating_add(height))?;
+            self.file.highlight(&self.highlighted_word, self.offset.y,
                 Some(self.offset.y.saturating_add(self.scr.size().height as usize)));
             self.draw_rows();
             self.draw_status_bar();
             self.draw_message_bar();
-            Screen::cursor_position(&Position {
-                x: self.cursor.x.saturating_sub(self.offset.x),
-                y: self.cursor.y.saturating_sub(self.offset.y),
-            });
+            let position = if self.wraps() {
+                let starts = self.wrap_starts(self.cursor.y);
+                let index = wrap_line(&starts, self.cursor.x);
+                let mut line = (self.cursor.y, index);
+                let mut y = 0;
+                while line > (self.offset.y, self.offset.x) && y < height {
+                    line = self.line_above(line);
+                    y += 1;
+                }
+                Position { x: self.visual_column(self.cursor.y, &starts, self.cursor.x), y }
+            } else {
+                Position {
+                    x: self.file.cursor_column(&self.cursor).saturating_sub(self.offset.x),
+                    y: self.cursor.y.saturating_sub(self.offset.y),
+                }
+            };
+            self.scr.present(&Position { x: position.x + self.gutter_width(), ..position })
         }
-        Screen::cursor_show();
-        Screen::flush()
     }
 
     fn prompt<Cb>(&mut self, prompt: &str, mut callback: Cb) -> Result<Option<String>, std::io::Error>
@@ -409,9 +1459,14 @@ impl Editor {
         loop {
             self.statusmsg = StatusMessage::from(format!("{}{}*", prompt, msg));
             self.refresh_screen()?;
-            let key = Screen::read_key()?;
+            let key = match self.next_key()? {
+                Some(key) => key,
+                None => continue,
+            };
             match key {
-                Key::Backspace => msg.truncate(msg.len().saturating_sub(1)),
+                Key::Backspace => {
+                    msg.pop();
+                },
                 Key::Char('\n') => {
                     break;
                 }
@@ -435,3 +1490,8 @@ impl Editor {
         Ok(Some(msg))
     }
 }
+
+///Which of the visual lines starting at `starts` column `x` of a wrapped row is on.
+fn wrap_line(starts: &[usize], x: usize) -> usize {
+    starts.iter().rposition(|&start| start <= x).unwrap_or(0)
+}
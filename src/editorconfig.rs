@@ -0,0 +1,328 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::parse_ini;
+
+const EDITORCONFIG_FILENAME: &str = ".editorconfig";
+
+///Properties from the `.editorconfig` files above `path` that apply to it, with values
+///lowercased. Files closer to `path` and later sections win, and `unset` drops a property.
+pub fn properties(path: &Path) -> Vec<(String, String)> {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        match env::current_dir() {
+            Ok(dir) => dir.join(path),
+            Err(_) => return Vec::new(),
+        }
+    };
+    let mut files = Vec::new();
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        let candidate = current.join(EDITORCONFIG_FILENAME);
+        if let Ok(contents) = fs::read_to_string(&candidate) {
+            let settings = parse_ini(&contents);
+            let root = settings.iter()
+                .any(|(section, key, value)| section.is_empty() && key == "root"
+                    && value.eq_ignore_ascii_case("true"));
+            files.push((current.to_path_buf(), settings));
+            if root {
+                break;
+            }
+        }
+        dir = current.parent();
+    }
+
+    let mut properties: Vec<(String, String)> = Vec::new();
+    for (dir, settings) in files.iter().rev() {
+        for (section, key, value) in settings {
+            if section.is_empty() || !section_matches(section, dir, &path) {
+                continue;
+            }
+            properties.retain(|(k, _)| k != key);
+            let value = value.to_ascii_lowercase();
+            if value != "unset" {
+                properties.push((key.clone(), value));
+            }
+        }
+    }
+
+    //defaults the spec derives from the other properties
+    let value = |properties: &[(String, String)], key: &str| {
+        properties.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone())
+    };
+    if let Some(size) = value(&properties, "indent_size").filter(|size| size != "tab") {
+        if value(&properties, "tab_width").is_none() {
+            properties.push(("tab_width".to_string(), size));
+        }
+    }
+    let tab_indent = value(&properties, "indent_style").is_some_and(|style| style == "tab");
+    if tab_indent && value(&properties, "indent_size").is_none() {
+        properties.push(("indent_size".to_string(), "tab".to_string()));
+    }
+    properties
+}
+
+///Whether the glob of a section of the `.editorconfig` in `dir` matches `path`. A glob
+///without a `/` matches file names in any directory, others are relative to `dir`.
+fn section_matches(glob: &str, dir: &Path, path: &Path) -> bool {
+    if glob.contains('/') {
+        let relative = match path.strip_prefix(dir) {
+            Ok(relative) => relative,
+            Err(_) => return false,
+        };
+        let relative: PathBuf = relative.components().collect();
+        let glob = glob.strip_prefix('/').unwrap_or(glob);
+        glob_matches(glob, &relative.to_string_lossy())
+    } else {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        glob_matches(glob, &name)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Token {
+    Char(char),
+    ///`*`, anything but a `/`
+    Star,
+    ///`**`, anything
+    DoubleStar,
+    ///`?`, any single character
+    Any,
+    ///`[abc]`, `[a-z]` or `[!abc]`
+    Class(Vec<(char, char)>, bool),
+    ///`{a,b,c}`
+    Alternatives(Vec<Vec<Token>>),
+    ///`{1..10}`, any integer between the two
+    Range(i64, i64),
+}
+
+///Matches `name` against an EditorConfig glob.
+fn glob_matches(glob: &str, name: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let tokens = parse_glob(&glob, &mut 0, false);
+    let name: Vec<char> = name.chars().collect();
+    match_tokens(&tokens, &name)
+}
+
+///Parses the glob from `*pos` on, stopping at a `,` or `}` that closes an alternative when
+///`nested` is set.
+fn parse_glob(glob: &[char], pos: &mut usize, nested: bool) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    while *pos < glob.len() {
+        let c = glob[*pos];
+        if nested && (c == ',' || c == '}') {
+            break;
+        }
+        *pos += 1;
+        match c {
+            '\\' if *pos < glob.len() => {
+                tokens.push(Token::Char(glob[*pos]));
+                *pos += 1;
+            },
+            '*' if glob.get(*pos) == Some(&'*') => {
+                tokens.push(Token::DoubleStar);
+                *pos += 1;
+            },
+            '*' => tokens.push(Token::Star),
+            '?' => tokens.push(Token::Any),
+            '[' => match parse_class(glob, pos) {
+                Some(class) => tokens.push(class),
+                None => tokens.push(Token::Char('[')),
+            },
+            '{' => match parse_braces(glob, pos) {
+                Some(braces) => tokens.push(braces),
+                None => tokens.push(Token::Char('{')),
+            },
+            c => tokens.push(Token::Char(c)),
+        }
+    }
+    tokens
+}
+
+///Parses a `[...]` class whose `[` is just before `*pos`, leaving `*pos` as it is if the
+///class is not closed.
+fn parse_class(glob: &[char], pos: &mut usize) -> Option<Token> {
+    let mut i = *pos;
+    let negated = matches!(glob.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = Vec::new();
+    let mut first = true;
+    loop {
+        let c = *glob.get(i)?;
+        if c == ']' && !first {
+            break;
+        }
+        first = false;
+        if glob.get(i + 1) == Some(&'-') && glob.get(i + 2).is_some_and(|&end| end != ']') {
+            ranges.push((c, glob[i + 2]));
+            i += 3;
+        } else {
+            ranges.push((c, c));
+            i += 1;
+        }
+    }
+    *pos = i + 1;
+    Some(Token::Class(ranges, negated))
+}
+
+///Parses a `{...}` group whose `{` is just before `*pos`, leaving `*pos` as it is if the
+///group is not closed.
+fn parse_braces(glob: &[char], pos: &mut usize) -> Option<Token> {
+    let close = (*pos..glob.len()).find(|&i| glob[i] == '}')?;
+    let inner: String = glob[*pos..close].iter().collect();
+    if let Some((start, end)) = inner.split_once("..") {
+        if let (Ok(start), Ok(end)) = (start.parse::<i64>(), end.parse::<i64>()) {
+            *pos = close + 1;
+            return Some(Token::Range(start.min(end), start.max(end)));
+        }
+    }
+    let mut i = *pos;
+    let mut alternatives = Vec::new();
+    loop {
+        alternatives.push(parse_glob(glob, &mut i, true));
+        match glob.get(i) {
+            Some(',') => i += 1,
+            Some('}') => break,
+            _ => return None,
+        }
+    }
+    *pos = i + 1;
+    Some(Token::Alternatives(alternatives))
+}
+
+fn match_tokens(tokens: &[Token], name: &[char]) -> bool {
+    let (token, rest) = match tokens.split_first() {
+        Some(split) => split,
+        None => return name.is_empty(),
+    };
+    match token {
+        Token::Char(c) => name.first() == Some(c) && match_tokens(rest, &name[1..]),
+        Token::Any => !name.is_empty() && match_tokens(rest, &name[1..]),
+        Token::Star => {
+            let limit = name.iter().position(|&c| c == '/').unwrap_or(name.len());
+            (0..=limit).any(|n| match_tokens(rest, &name[n..]))
+        },
+        Token::DoubleStar => (0..=name.len()).any(|n| match_tokens(rest, &name[n..])),
+        Token::Class(ranges, negated) => match name.first() {
+            Some(&c) => {
+                let found = ranges.iter().any(|&(start, end)| start <= c && c <= end);
+                found != *negated && match_tokens(rest, &name[1..])
+            },
+            None => false,
+        },
+        Token::Alternatives(alternatives) => alternatives.iter().any(|alternative| {
+            let mut tokens = alternative.clone();
+            tokens.extend_from_slice(rest);
+            match_tokens(&tokens, name)
+        }),
+        Token::Range(start, end) => {
+            let sign = usize::from(name.first() == Some(&'-'));
+            let digits = name[sign..].iter().take_while(|c| c.is_ascii_digit()).count();
+            (1..=digits).any(|n| {
+                let number: String = name[..sign + n].iter().collect();
+                number.parse::<i64>().is_ok_and(|number| *start <= number && number <= *end)
+                    && match_tokens(rest, &name[sign + n..])
+            })
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_stops_at_slashes() {
+        assert!(glob_matches("*", "main.rs"));
+        assert!(glob_matches("*.rs", "main.rs"));
+        assert!(glob_matches("*.rs", ".rs"));
+        assert!(!glob_matches("*.rs", "main.c"));
+        assert!(!glob_matches("*.rs", "src/main.rs"));
+        assert!(glob_matches("src/*.rs", "src/main.rs"));
+        assert!(!glob_matches("src/*.rs", "src/bin/main.rs"));
+    }
+
+    #[test]
+    fn double_star_crosses_slashes() {
+        assert!(glob_matches("**.rs", "src/bin/main.rs"));
+        assert!(glob_matches("src/**.rs", "src/bin/main.rs"));
+        assert!(glob_matches("src/**/main.rs", "src/bin/main.rs"));
+        assert!(!glob_matches("src/**.rs", "tests/main.rs"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(glob_matches("?.c", "a.c"));
+        assert!(!glob_matches("?.c", "ab.c"));
+        assert!(!glob_matches("?.c", ".c"));
+    }
+
+    #[test]
+    fn alternatives() {
+        assert!(glob_matches("*.{c,h}", "main.c"));
+        assert!(glob_matches("*.{c,h}", "main.h"));
+        assert!(!glob_matches("*.{c,h}", "main.rs"));
+        assert!(glob_matches("{Makefile,*.mk}", "Makefile"));
+        assert!(glob_matches("{Makefile,*.mk}", "rules.mk"));
+        assert!(glob_matches("{src/{a,b},lib}.rs", "src/b.rs"));
+        //an empty alternative matches an empty string
+        assert!(glob_matches("main{,.rs}", "main"));
+        //not closed, so a literal brace
+        assert!(glob_matches("{a,b", "{a,b"));
+        assert!(!glob_matches("{a,b", "a"));
+    }
+
+    #[test]
+    fn number_ranges() {
+        assert!(glob_matches("file{1..3}.txt", "file2.txt"));
+        assert!(glob_matches("file{3..1}.txt", "file1.txt"));
+        assert!(!glob_matches("file{1..3}.txt", "file4.txt"));
+        assert!(glob_matches("{-5..5}", "-5"));
+        assert!(!glob_matches("file{1..3}.txt", "file.txt"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(glob_matches("[abc].txt", "b.txt"));
+        assert!(!glob_matches("[abc].txt", "d.txt"));
+        assert!(glob_matches("[a-c].txt", "c.txt"));
+        assert!(!glob_matches("[!a-c].txt", "a.txt"));
+        assert!(glob_matches("[!a-c].txt", "d.txt"));
+        assert!(glob_matches("[]].txt", "].txt"));
+        assert!(glob_matches("[a-].txt", "-.txt"));
+        //not closed, so a literal bracket
+        assert!(glob_matches("[abc", "[abc"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(glob_matches("\\*.txt", "*.txt"));
+        assert!(!glob_matches("\\*.txt", "a.txt"));
+        assert!(glob_matches("\\{a\\}", "{a}"));
+    }
+
+    #[test]
+    fn sections_without_slash_match_file_names_anywhere() {
+        let dir = Path::new("/project");
+        assert!(section_matches("*.rs", dir, Path::new("/project/main.rs")));
+        assert!(section_matches("*.rs", dir, Path::new("/project/src/bin/main.rs")));
+        assert!(section_matches("Makefile", dir, Path::new("/project/sub/Makefile")));
+        assert!(!section_matches("*.rs", dir, Path::new("/project/main.c")));
+    }
+
+    #[test]
+    fn sections_with_slash_are_relative_to_their_file() {
+        let dir = Path::new("/project");
+        assert!(section_matches("src/*.rs", dir, Path::new("/project/src/main.rs")));
+        assert!(section_matches("/src/*.rs", dir, Path::new("/project/src/main.rs")));
+        assert!(!section_matches("src/*.rs", dir, Path::new("/project/lib/src/main.rs")));
+        assert!(!section_matches("src/*.rs", dir, Path::new("/other/src/main.rs")));
+        assert!(section_matches("src/**.rs", dir, Path::new("/project/src/bin/main.rs")));
+        assert!(section_matches("/*.md", dir, Path::new("/project/README.md")));
+        assert!(!section_matches("/*.md", dir, Path::new("/project/docs/README.md")));
+    }
+}
@@ -0,0 +1,124 @@
+use std::io::{Error, ErrorKind};
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+///Character encoding of a file on disk. The buffer itself is always UTF-8, the encoding
+///is only used when converting to and from the bytes in the file.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl Encoding {
+    ///Guesses the encoding of `bytes` from its byte order mark, if any. Files without a BOM
+    ///are read as UTF-8 when they are valid UTF-8, and as Latin-1 otherwise, since every
+    ///byte sequence is valid Latin-1.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(UTF8_BOM) {
+            Encoding::Utf8Bom
+        } else if bytes.starts_with(UTF16LE_BOM) {
+            Encoding::Utf16Le
+        } else if bytes.starts_with(UTF16BE_BOM) {
+            Encoding::Utf16Be
+        } else if std::str::from_utf8(bytes).is_ok() {
+            Encoding::Utf8
+        } else {
+            Encoding::Latin1
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-8-bom" | "utf8-bom" => Some(Encoding::Utf8Bom),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+
+    pub fn to_enum_str(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf8Bom => "utf-8-bom",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin1",
+        }
+    }
+
+    ///Converts the raw file contents to a string, dropping the byte order mark.
+    pub fn decode(self, bytes: &[u8]) -> Result<String, Error> {
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => {
+                let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
+                String::from_utf8(bytes.to_vec())
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+            },
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let bom = if self == Encoding::Utf16Le { UTF16LE_BOM } else { UTF16BE_BOM };
+                let bytes = bytes.strip_prefix(bom).unwrap_or(bytes);
+                if bytes.len() % 2 != 0 {
+                    return Err(Error::new(ErrorKind::InvalidData,
+                        format!("odd number of bytes in {} file", self.to_enum_str())));
+                }
+                let units = bytes.chunks(2).map(|pair| {
+                    if self == Encoding::Utf16Le {
+                        u16::from_le_bytes([pair[0], pair[1]])
+                    } else {
+                        u16::from_be_bytes([pair[0], pair[1]])
+                    }
+                });
+                char::decode_utf16(units)
+                    .collect::<Result<String, _>>()
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+            },
+            Encoding::Latin1 => Ok(bytes.iter().map(|&b| b as char).collect()),
+        }
+    }
+
+    ///Converts `text` to the bytes written to disk, including the byte order mark.
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, Error> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Utf8Bom => {
+                let mut bytes = UTF8_BOM.to_vec();
+                bytes.extend_from_slice(text.as_bytes());
+                Ok(bytes)
+            },
+            Encoding::Utf16Le => {
+                let mut bytes = UTF16LE_BOM.to_vec();
+                for unit in text.encode_utf16() {
+                    bytes.extend_from_slice(&unit.to_le_bytes());
+                }
+                Ok(bytes)
+            },
+            Encoding::Utf16Be => {
+                let mut bytes = UTF16BE_BOM.to_vec();
+                for unit in text.encode_utf16() {
+                    bytes.extend_from_slice(&unit.to_be_bytes());
+                }
+                Ok(bytes)
+            },
+            Encoding::Latin1 => {
+                let mut bytes = Vec::with_capacity(text.len());
+                for c in text.chars() {
+                    if (c as u32) > 0xFF {
+                        return Err(Error::new(ErrorKind::InvalidData,
+                            format!("'{}' cannot be encoded as latin1", c)));
+                    }
+                    bytes.push(c as u8);
+                }
+                Ok(bytes)
+            },
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::io::{stdin, Error, ErrorKind};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use signal_hook::consts::{SIGHUP, SIGTERM, SIGWINCH};
+use signal_hook::iterator::Signals;
+use termion::event::Key;
+use termion::input::TermRead;
+
+use crate::editor::Editor;
+
+///Something the editor has to react to.
+pub enum Event {
+    Key(Key),
+    ///Reading the terminal failed, or it went away
+    InputError(Error),
+    ///A signal asking us to go away, e.g. SIGHUP when the terminal is closed
+    Signal(i32),
+    ///The terminal was resized
+    Resize,
+    ///A background job finished, this hands its result to the editor
+    Job(Box<dyn FnOnce(&mut Editor) + Send>),
+}
+
+///A single queue fed by threads reading the terminal and waiting for signals, and by
+///background jobs, so that the editor can wait for all of them at once and still wake up
+///when one of its timers is due.
+pub struct Events {
+    sender: Sender<Event>,
+    receiver: Receiver<Event>,
+}
+
+impl Events {
+    pub fn new() -> Result<Self, Error> {
+        let (sender, receiver) = mpsc::channel();
+
+        let input = sender.clone();
+        thread::spawn(move || {
+            //one iterator for the whole session, so no bytes are lost between keys
+            for key in stdin().keys() {
+                let event = match key {
+                    Ok(key) => Event::Key(key),
+                    Err(e) => Event::InputError(e),
+                };
+                let failed = matches!(event, Event::InputError(_));
+                if input.send(event).is_err() || failed {
+                    return;
+                }
+            }
+            let closed = Error::new(ErrorKind::UnexpectedEof, "terminal closed");
+            let _ = input.send(Event::InputError(closed));
+        });
+
+        let mut signals = Signals::new([SIGHUP, SIGTERM, SIGWINCH])?;
+        let signal = sender.clone();
+        thread::spawn(move || {
+            for number in signals.forever() {
+                let event = match number {
+                    SIGWINCH => Event::Resize,
+                    number => Event::Signal(number),
+                };
+                if signal.send(event).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(Self { sender, receiver })
+    }
+
+    ///Waits for the next event, giving up after `timeout`.
+    pub fn next(&self, timeout: Duration) -> Option<Event> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    ///Runs `job` on a thread of its own, and once it is done hands its result to `done` on
+    ///the editor's thread.
+    pub fn spawn<T, J, D>(&self, job: J, done: D) -> JoinHandle<()>
+    where
+        T: Send + 'static,
+        J: FnOnce() -> T + Send + 'static,
+        D: FnOnce(&mut Editor, T) + Send + 'static,
+    {
+        let sender = self.sender.clone();
+        thread::spawn(move || {
+            let result = job();
+            let _ = sender.send(Event::Job(Box::new(move |editor| done(editor, result))));
+        })
+    }
+}
@@ -1,12 +1,25 @@
 use std::fs;
-use std::io::{Error, Write};
+use std::mem;
+use std::io::{Error, ErrorKind, Write};
 use std::fmt;
-use std::path::Path;
-use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+use std::ffi::{CString, OsStr};
+use std::os::unix::fs::{fchown, MetadataExt, OpenOptionsExt};
 
+use crate::backup;
+use crate::compression::Compression;
+use crate::config::{Config, IndentStyle, LineNumbers, SoftWrap};
 use crate::editor::SearchDirection;
+use crate::encoding::Encoding;
+use crate::hex;
+use crate::large::LargeFile;
+use crate::listing;
+use crate::rope::Rope;
 use crate::row::Row;
 use crate::screen::Position;
+use crate::swap::{self, Swap};
 
 #[derive(Clone, Copy, Debug)]
 pub enum FileType {
@@ -37,6 +50,49 @@ impl FileType {
     }
 }
 
+///Line terminator used when writing the file back to disk.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    ///Picks the style used by the majority of lines in `contents`.
+    pub fn detect(contents: &str) -> Self {
+        let crlf = contents.matches("\r\n").count();
+        let lf = contents.matches('\n').count() - crlf;
+        if crlf > lf {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "lf" | "unix" => Some(LineEnding::Lf),
+            "crlf" | "dos" => Some(LineEnding::CrLf),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+
+    pub fn to_enum_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+        }
+    }
+}
+
 ///Contains metadata used for syntax highlighting in a given file.
 #[derive(Default)]
 pub struct HighlightOptions {
@@ -184,6 +240,7 @@ impl HighlightOptions {
     }
 
     pub fn set_filetype(filename: &str) -> Option<FileType> {
+        let filename = Compression::strip_extension(filename);
         let extension = Path::new(&filename).extension().and_then(OsStr::to_str);
 
         match extension {
@@ -205,55 +262,811 @@ impl HighlightOptions {
     }
 }
 
+///What the file looked like on disk when we last read or wrote it, used to notice other
+///programs changing it behind our back.
+#[derive(Clone, Copy, PartialEq)]
+struct DiskStamp {
+    modified: SystemTime,
+    len: u64,
+}
+
+impl DiskStamp {
+    fn read(path: &str) -> Option<Self> {
+        let meta = fs::metadata(path).ok()?;
+        Some(Self {
+            modified: meta.modified().ok()?,
+            len: meta.len(),
+        })
+    }
+}
+
+///Whether we are allowed to write to `path`. Files that don't exist yet count as writable,
+///creating them will fail on save if it isn't.
+fn is_writable(path: &str) -> bool {
+    let path = match CString::new(path) {
+        Ok(path) => path,
+        Err(_) => return true,
+    };
+    let writable = unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0;
+    writable || Error::last_os_error().kind() == ErrorKind::NotFound
+}
+
+///Follows `path` through any symlinks, so that saving replaces the file they point to
+///rather than the links themselves. Dangling links resolve to the missing target.
+fn resolve_symlinks(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    //same limit as the kernel's, so that a symlink loop doesn't hang the save
+    for _ in 0..40 {
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                if let Ok(target) = fs::read_link(&path) {
+                    path = match path.parent() {
+                        Some(parent) => parent.join(target),
+                        None => target,
+                    };
+                } else {
+                    break;
+                }
+            },
+            _ => break,
+        }
+    }
+    path
+}
+
+///Writes `bytes` to a temporary file next to `path` and renames it over `path`, so the
+///original is never left half-written. The original file's permissions are kept, and its
+///owner and group as far as we are allowed to set them. Since `path` is replaced by a new
+///file, other hard links to it keep pointing at the old contents.
+fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    let target = resolve_symlinks(path);
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = target.file_name().and_then(OsStr::to_str).unwrap_or("hecto");
+    let tmp_path = dir.join(format!(".{}.hecto-{}.tmp", name, process::id()));
+
+    let result = (|| {
+        let original = fs::metadata(&target).ok();
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        if original.is_some() {
+            //nobody else may read it until it has the original's permissions
+            options.mode(0o600);
+        }
+        let mut tmp = options.open(&tmp_path)?;
+        if let Some(meta) = &original {
+            //only root can give a file away, otherwise it just stays ours
+            let _ = fchown(&tmp, Some(meta.uid()), Some(meta.gid()));
+            tmp.set_permissions(meta.permissions())?;
+        }
+        tmp.write_all(bytes)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &target)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+        return result;
+    }
+    //make the rename itself durable
+    if let Ok(dir) = fs::File::open(&dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+///Rewrites the whitespace at the start of `line` as `style`, keeping its width.
+fn convert_indent(line: &str, style: IndentStyle, tab_width: usize) -> String {
+    let rest = line.trim_start_matches([' ', '\t']);
+    let width = indent_width(&line[..line.len() - rest.len()], tab_width);
+    let indent = match style {
+        IndentStyle::Spaces => " ".repeat(width),
+        IndentStyle::Tabs => "\t".repeat(width / tab_width) + &" ".repeat(width % tab_width),
+    };
+    indent + rest
+}
+
+///Number of columns taken by `indent`, made of spaces and tabs.
+fn indent_width(indent: &str, tab_width: usize) -> usize {
+    indent.chars().fold(0, |width, c| {
+        if c == '\t' {
+            (width / tab_width + 1) * tab_width
+        } else {
+            width + 1
+        }
+    })
+}
+
+///Where the character at `x` in `old` went in `new`, when `new` only differs from `old` in
+///its indentation and in the whitespace at its end.
+fn map_column(old: &str, new: &Row, x: usize, tab_width: usize) -> usize {
+    let old_indent = old.len() - old.trim_start_matches([' ', '\t']).len();
+    let new_str = new.as_str();
+    let new_indent = new_str.len() - new_str.trim_start_matches([' ', '\t']).len();
+    let x = if x >= old_indent {
+        x - old_indent + new_indent
+    } else {
+        //inside the indentation, stay on the same screen column
+        let column = indent_width(&old[..x], tab_width);
+        (0..=new_indent)
+            .take_while(|&i| indent_width(&new_str[..i], tab_width) <= column)
+            .last()
+            .unwrap_or(0)
+    };
+    x.min(new.len())
+}
+
 pub struct File {
-    rows: Vec<Row>,
+    rows: Rope,
     pub filename: Option<String>,
     pub dirty: bool,
     hl_opts: HighlightOptions,
+    //rows before this one have up to date highlighting, except for search matches when
+    //they were highlighted in an older generation
+    hl_valid_until: usize,
+    //search match the rows are highlighted with
+    hl_word: Option<String>,
+    //bumped whenever `hl_word` changes, so that rows can tell their matches are stale
+    //without going over all of them
+    hl_generation: u64,
+    line_ending: LineEnding,
+    final_newline: bool,
+    encoding: Encoding,
+    config: Config,
+    version: u64,
+    disk_stamp: Option<DiskStamp>,
+    large: Option<LargeFile>,
+    //index of the first row in `rows`, only non zero in large file mode
+    window_start: usize,
+    //contents of a binary file, `rows` are a hex dump of it
+    hex: Option<Vec<u8>>,
+    //the file can't be written, as far as we can tell
+    read_only: bool,
+    //the user asked for the buffer to be read-only, whatever file it points at
+    forced_read_only: bool,
+    //format the file is compressed with on disk, if any
+    compression: Option<Compression>,
+    //paths of the entries of a directory listing, one per row
+    listing: Option<Vec<PathBuf>>,
+}
+
+///The file a buffer pointed at and the settings that came with it, as given back by
+///`File::set_filename`.
+pub struct FileName {
+    filename: Option<String>,
+    config: Config,
+    read_only: bool,
+    compression: Option<Compression>,
+    disk_stamp: Option<DiskStamp>,
 }
 
 impl File {
     pub fn open(filename: &str) -> Result<Self, std::io::Error>{
-        let mut rows : Vec<Row> = Vec::new();
-        let contents = fs::read_to_string(filename)?;
-        for line in contents.lines() {
-            rows.push(Row::from(line));
+        Self::open_with_encoding(filename, None)
+    }
+
+    ///Opens `filename`, decoding it with `encoding` or with the detected encoding if `None`.
+    pub fn open_with_encoding(filename: &str, encoding: Option<Encoding>) -> Result<Self, std::io::Error>{
+        let mut file = Self::read(filename, encoding)?;
+        file.read_only = !is_writable(filename);
+        Ok(file)
+    }
+
+    fn read(filename: &str, encoding: Option<Encoding>) -> Result<Self, std::io::Error>{
+        let config = Config::load(Some(filename));
+        let metadata = fs::metadata(filename)?;
+        if metadata.is_dir() {
+            return Self::open_listing(filename, config);
         }
+        let size = metadata.len();
+        let compression = Compression::sniff(filename)?;
+        //a compressed file has to be decompressed as a whole, there is no indexing it
+        if size > config.large_file_size && compression.is_none() {
+            match Self::open_large(filename, encoding, config.clone()) {
+                Err(e) if e.kind() == ErrorKind::Unsupported => {},
+                result => return result,
+            }
+        }
+        let bytes = match compression {
+            Some(compression) => compression.decompress(&fs::read(filename)?)?,
+            None => fs::read(filename)?,
+        };
+        let hl_opts = if bytes.len() as u64 > config.highlight_max_size {
+            HighlightOptions::default()
+        } else {
+            HighlightOptions::from(filename)
+        };
+        if encoding.is_none() && hex::is_binary(&bytes) {
+            return Ok(Self {
+                filename: Some(String::from(filename)),
+                rows: hex::dump(&bytes).into(),
+                config,
+                disk_stamp: DiskStamp::read(filename),
+                hex: Some(bytes),
+                compression,
+                ..Self::default()
+            });
+        }
+        //a configured charset the file isn't valid in is ignored, an explicit encoding isn't
+        let decoded = match (encoding, config.charset) {
+            (Some(encoding), _) => Some((encoding, encoding.decode(&bytes)?)),
+            (None, Some(charset)) => charset.decode(&bytes).ok().map(|contents| (charset, contents)),
+            (None, None) => None,
+        };
+        let (encoding, contents) = match decoded {
+            Some(decoded) => decoded,
+            None => {
+                let encoding = Encoding::detect(&bytes);
+                (encoding, encoding.decode(&bytes)?)
+            },
+        };
+        let text = Self::from_text(&contents);
 
         Ok(Self {
-            rows: rows,
             filename: Some(String::from(filename)),
-            dirty: false,
-            hl_opts: HighlightOptions::from(filename)
+            hl_opts,
+            encoding,
+            line_ending: config.end_of_line.unwrap_or(text.line_ending),
+            config,
+            disk_stamp: DiskStamp::read(filename),
+            compression,
+            ..text
+        })
+    }
+
+    ///Opens `filename` in large file mode: nothing is read up front, rows are loaded by
+    ///`load_rows` as they scroll into view. Highlighting is disabled, and the whole file is
+    ///only read in by `load_all` when it is edited or saved.
+    fn open_large(filename: &str, encoding: Option<Encoding>, config: Config) -> Result<Self, std::io::Error> {
+        let large = LargeFile::open(filename, encoding)?;
+        Ok(Self {
+            filename: Some(String::from(filename)),
+            line_ending: large.line_ending(),
+            final_newline: large.has_final_newline(),
+            encoding: large.encoding(),
+            config,
+            disk_stamp: DiskStamp::read(filename),
+            large: Some(large),
+            ..Self::default()
+        })
+    }
+
+    ///Opens the directory `filename` as a listing of its entries. The buffer is named after
+    ///the canonical path of the directory, so that its parent can always be found.
+    fn open_listing(filename: &str, config: Config) -> Result<Self, std::io::Error> {
+        let dir = fs::canonicalize(filename)?;
+        let entries = listing::read(&dir)?;
+        let dirname = dir.to_string_lossy().into_owned();
+        Ok(Self {
+            rows: entries.iter().map(|entry| listing::row(&dir, entry)).collect(),
+            disk_stamp: DiskStamp::read(&dirname),
+            filename: Some(dirname),
+            config,
+            listing: Some(entries),
+            ..Self::default()
         })
     }
 
+    ///Builds an unnamed buffer holding `contents`.
+    pub fn from_text(contents: &str) -> Self {
+        let mut rows : Vec<Row> = Vec::new();
+        let line_ending = LineEnding::detect(contents);
+        let final_newline = contents.is_empty() || contents.ends_with('\n');
+        //split manually rather than with lines(), so that a stray '\r' in a LF file survives a save
+        let body = contents.strip_suffix('\n').unwrap_or(contents);
+        if !contents.is_empty() {
+            for line in body.split('\n') {
+                let line = if line_ending == LineEnding::CrLf {
+                    line.strip_suffix('\r').unwrap_or(line)
+                } else {
+                    line
+                };
+                rows.push(Row::from(line));
+            }
+        }
+
+        Self {
+            rows: rows.into(),
+            line_ending,
+            final_newline,
+            ..Self::default()
+        }
+    }
+
     pub fn len(&self) -> usize {
-        self.rows.len()
+        match &self.large {
+            Some(large) => large.line_count(),
+            None => self.rows.len(),
+        }
+    }
+
+    pub fn is_large(&self) -> bool {
+        self.large.is_some()
+    }
+
+    ///Whether changes to the buffer are refused.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only || self.forced_read_only || self.is_listing()
+    }
+
+    ///Whether the user made the buffer read-only, rather than the file being unwritable.
+    pub fn is_forced_read_only(&self) -> bool {
+        self.forced_read_only
+    }
+
+    ///Makes the buffer read-only until told otherwise, even when it is pointed at another
+    ///file, or allows changes to it again.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.forced_read_only = read_only;
+        if !read_only {
+            self.read_only = false;
+        }
+    }
+
+    ///Whether this is a binary file shown as a hex dump. The rows then hold the dump, and
+    ///the cursor's x is the index of a hex digit in its row.
+    pub fn is_hex(&self) -> bool {
+        self.hex.is_some()
+    }
+
+    ///Number of columns the Tab key indents to when indenting with spaces, or `None` when
+    ///it inserts a tab.
+    pub fn soft_tab(&self) -> Option<usize> {
+        match self.config.indent_style {
+            Some(IndentStyle::Spaces) => Some(self.config.indent_size.unwrap_or(self.config.tab_width)),
+            _ => None,
+        }
+    }
+
+    pub fn line_numbers(&self) -> LineNumbers {
+        self.config.line_numbers
+    }
+
+    pub fn soft_wrap(&self) -> SoftWrap {
+        self.config.soft_wrap
+    }
+
+    pub fn show_whitespace(&self) -> bool {
+        self.config.show_whitespace
+    }
+
+    pub fn highlight_current_line(&self) -> bool {
+        self.config.highlight_current_line
+    }
+
+    pub fn rulers(&self) -> &[usize] {
+        &self.config.rulers
+    }
+
+    pub fn is_listing(&self) -> bool {
+        self.listing.is_some()
+    }
+
+    ///Path of the entry shown on row `y` of a directory listing.
+    pub fn listing_entry(&self, y: usize) -> Option<&Path> {
+        self.listing.as_ref()?.get(y).map(PathBuf::as_path)
+    }
+
+    ///Row of a directory listing on which `path` is shown.
+    pub fn listing_row(&self, path: &Path) -> Option<usize> {
+        self.listing.as_ref()?.iter().position(|entry| entry == path)
+    }
+
+    ///Largest x the cursor can have on row `y`.
+    pub fn row_width(&self, y: usize) -> usize {
+        match &self.hex {
+            Some(bytes) => {
+                let row_bytes = bytes.len().saturating_sub(y * hex::BYTES_PER_ROW).min(hex::BYTES_PER_ROW);
+                (row_bytes * 2).saturating_sub(1)
+            },
+            None => self.row(y).map_or(0, Row::len),
+        }
+    }
+
+    ///Column of the rendered row `at.y` at which the cursor is drawn.
+    pub fn cursor_column(&self, at: &Position) -> usize {
+        if self.is_hex() {
+            hex::column(at.x)
+        } else {
+            self.row(at.y).map_or(at.x, |row| row.column(at.x, self.config.tab_width))
+        }
+    }
+
+    ///The x the cursor gets on row `y` to be drawn at, or just before, screen column
+    ///`column`. The inverse of `cursor_column`.
+    pub fn cursor_at(&self, y: usize, column: usize) -> usize {
+        if self.is_hex() {
+            hex::nibble(column).min(self.row_width(y))
+        } else {
+            self.row(y).map_or(0, |row| row.index_at(column, self.config.tab_width))
+        }
+    }
+
+    ///Screen columns between tab stops.
+    pub fn tab_width(&self) -> usize {
+        self.config.tab_width
+    }
+
+    ///Makes sure rows `start..end` are in memory. Only large files need this, other files
+    ///are always fully loaded. Rows around the requested ones are loaded too, so that
+    ///scrolling a little doesn't go back to the disk every time.
+    pub fn load_rows(&mut self, start: usize, end: usize) -> Result<(), Error> {
+        let large = match &self.large {
+            Some(large) => large,
+            None => return Ok(()),
+        };
+        let end = end.min(large.line_count());
+        if start >= self.window_start && end <= self.window_start + self.rows.len() {
+            return Ok(());
+        }
+        let margin = end.saturating_sub(start).max(1);
+        let window_start = start.saturating_sub(margin);
+        let lines = large.read_lines(window_start, end.saturating_add(margin))?;
+        self.rows = lines.iter().map(|line| Row::from(&line[..])).collect();
+        self.window_start = window_start;
+        self.unhighlight_rows(0);
+        Ok(())
+    }
+
+    ///Reads all the rows of a large file into memory, leaving large file mode, so that it
+    ///can be edited and saved like any other file.
+    pub fn load_all(&mut self) -> Result<(), Error> {
+        let large = match &self.large {
+            Some(large) => large,
+            None => return Ok(()),
+        };
+        let lines = large.read_lines(0, large.line_count())?;
+        self.rows = lines.iter().map(|line| Row::from(&line[..])).collect();
+        self.large = None;
+        self.window_start = 0;
+        self.unhighlight_rows(0);
+        Ok(())
     }
 
     pub fn file_type(&self) -> Option<FileType> {
         self.hl_opts.file_type
     }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    pub fn has_final_newline(&self) -> bool {
+        self.final_newline
+    }
+
+    ///Backups of this file kept by previous saves, newest first.
+    pub fn backups(&self) -> Vec<PathBuf> {
+        match &self.filename {
+            Some(filename) => backup::list(&resolve_symlinks(Path::new(filename)), &self.config),
+            None => Vec::new(),
+        }
+    }
+
+    ///Replaces the text of this buffer with the text of `other`, e.g. a backup or a
+    ///recovered copy, while keeping the file name and settings. The buffer is marked dirty
+    ///since the file on disk no longer matches it.
+    pub fn replace_contents(&mut self, other: File) {
+        //the new rows are all in memory, so this also leaves large file mode
+        self.large = None;
+        self.hex = None;
+        self.window_start = 0;
+        self.rows = other.rows;
+        self.unhighlight_rows(0);
+        self.line_ending = other.line_ending;
+        self.final_newline = other.final_newline;
+        self.mark_dirty();
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    ///Sets the encoding used on the next save.
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        if self.encoding != encoding && !self.is_read_only() && !self.is_hex() {
+            self.encoding = encoding;
+            self.mark_dirty();
+        }
+    }
+
+    ///Converts the whole file to the given line ending style. The rows themselves are not
+    ///touched, only what is written between them on save.
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        if self.line_ending != line_ending && !self.is_read_only() && !self.is_hex() {
+            self.line_ending = line_ending;
+            self.mark_dirty();
+        }
+    }
     
     pub fn default() -> Self {
         Self {
-            rows: Vec::new(),
+            rows: Rope::default(),
             filename: None,
             dirty: false,
-            hl_opts: Default::default()
+            hl_opts: Default::default(),
+            hl_valid_until: 0,
+            hl_word: None,
+            hl_generation: 0,
+            line_ending: Default::default(),
+            final_newline: true,
+            encoding: Default::default(),
+            config: Config::load(None),
+            version: 0,
+            disk_stamp: None,
+            large: None,
+            window_start: 0,
+            hex: None,
+            read_only: false,
+            forced_read_only: false,
+            compression: None,
+            listing: None,
+        }
+    }
+
+    fn mark_dirty(&mut self) {
+        self.dirty = true;
+        self.version = self.version.wrapping_add(1);
+    }
+
+    ///Counter bumped on every change to the buffer, to tell whether it changed since
+    ///some earlier point.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    ///The text of the buffer as it would be written to disk, before encoding.
+    pub fn contents(&self) -> String {
+        let ending = self.line_ending.as_str();
+        let mut contents = String::new();
+        for (index, row) in self.rows.iter().enumerate() {
+            contents.push_str(row.as_str());
+            if index + 1 < self.rows.len() || self.final_newline {
+                contents.push_str(ending);
+            }
+        }
+        contents
+    }
+
+    ///Points the buffer at another file, as for Save As. The file type and the settings are
+    ///detected again for the new name. Returns what the buffer pointed at before, for
+    ///`restore_filename`.
+    pub fn set_filename(&mut self, filename: &str) -> FileName {
+        let old = FileName {
+            filename: self.filename.replace(String::from(filename)),
+            config: mem::replace(&mut self.config, Config::load(Some(filename))),
+            read_only: self.read_only,
+            compression: self.compression,
+            disk_stamp: self.disk_stamp,
+        };
+        if !self.is_hex() {
+            self.hl_opts = HighlightOptions::from(filename);
+            self.rehighlight_all();
+        }
+        self.read_only = !is_writable(filename);
+        self.compression = Compression::from_filename(filename);
+        //whatever is there now is about to be overwritten, on purpose
+        self.disk_stamp = DiskStamp::read(filename);
+        old
+    }
+
+    ///Points the buffer back at the file it pointed at before `set_filename`, e.g. after
+    ///saving under the new name failed.
+    pub fn restore_filename(&mut self, old: FileName) {
+        if !self.is_hex() {
+            self.hl_opts = match &old.filename {
+                Some(filename) => HighlightOptions::from(filename),
+                None => HighlightOptions::default(),
+            };
+            self.rehighlight_all();
+        }
+        self.filename = old.filename;
+        self.config = old.config;
+        self.read_only = old.read_only;
+        self.compression = old.compression;
+        self.disk_stamp = old.disk_stamp;
+    }
+
+    ///Moves the file on disk to `filename`, and points the buffer at it.
+    pub fn rename(&mut self, filename: &str) -> Result<(), Error> {
+        if let Some(old) = &self.filename {
+            if Path::new(old).exists() {
+                fs::rename(old, filename)?;
+            }
+        }
+        self.set_filename(filename);
+        Ok(())
+    }
+
+    ///Writes rows `start..end` to `filename`, in the encoding and with the line endings of
+    ///this file. Returns the number of bytes written.
+    pub fn write_range(&self, start: usize, end: usize, filename: &str) -> Result<usize, Error> {
+        let end = end.min(self.len());
+        if start >= end {
+            return Err(Error::new(ErrorKind::InvalidInput, "no lines in range"));
+        }
+        let bytes = if let Some(bytes) = &self.hex {
+            let from = (start * hex::BYTES_PER_ROW).min(bytes.len());
+            let to = (end * hex::BYTES_PER_ROW).min(bytes.len());
+            bytes[from..to].to_vec()
+        } else {
+            let lines = match &self.large {
+                Some(large) => large.read_lines(start, end)?,
+                None => self.rows.range(start, end).map(|row| row.as_str().to_string()).collect(),
+            };
+            let mut contents = String::new();
+            for line in lines {
+                contents.push_str(&line);
+                contents.push_str(self.line_ending.as_str());
+            }
+            self.encoding.encode(&contents)?
+        };
+        let bytes = match Compression::from_filename(filename) {
+            Some(compression) => compression.compress(&bytes)?,
+            None => bytes,
+        };
+        write_atomic(Path::new(filename), &bytes)?;
+        Ok(bytes.len())
+    }
+
+    ///Whether the file on disk was modified or replaced since we last read or wrote it.
+    pub fn changed_on_disk(&self) -> bool {
+        match (&self.filename, self.disk_stamp) {
+            (Some(filename), Some(stamp)) => {
+                DiskStamp::read(filename).is_some_and(|current| current != stamp)
+            },
+            _ => false,
+        }
+    }
+
+    ///Accepts the current state of the file on disk, so that `changed_on_disk` only reports
+    ///changes made after this point.
+    pub fn acknowledge_disk_change(&mut self) {
+        if let Some(filename) = &self.filename {
+            self.disk_stamp = DiskStamp::read(filename);
         }
     }
 
+    ///Text of the file on disk, decoded with the encoding of the buffer.
+    pub fn disk_contents(&self) -> Result<String, Error> {
+        match self.read_disk()? {
+            Some(file) => Ok(file.contents()),
+            None => Ok(String::new()),
+        }
+    }
+
+    ///Replaces the buffer with the file on disk, throwing away any unsaved changes.
+    pub fn reload(&mut self) -> Result<(), Error> {
+        if let Some(mut file) = self.read_disk()? {
+            file.version = self.version.wrapping_add(1);
+            file.read_only = self.read_only;
+            file.forced_read_only = self.forced_read_only;
+            *self = file;
+        }
+        Ok(())
+    }
+
+    ///Opens the file again the same way it was opened before.
+    fn read_disk(&self) -> Result<Option<File>, Error> {
+        match &self.filename {
+            Some(filename) => {
+                let encoding = if self.is_hex() { None } else { Some(self.encoding) };
+                File::open_with_encoding(filename, encoding).map(Some)
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn swap_path(&self) -> Option<PathBuf> {
+        if self.is_listing() {
+            return None;
+        }
+        self.filename.as_ref().map(|name| swap::path(&resolve_symlinks(Path::new(name))))
+    }
+
+    ///The swap file of the buffer and the unsaved contents to write to it, so that the
+    ///write can happen away from the buffer.
+    pub fn swap_snapshot(&self) -> Option<(PathBuf, String)> {
+        if self.is_hex() {
+            //swap files hold text, there is no way to get the bytes back from them
+            return None;
+        }
+        Some((self.swap_path()?, self.contents()))
+    }
+
+    ///Swap file left behind for this file, e.g. by a hecto that crashed.
+    pub fn read_swap(&self) -> Result<Option<Swap>, Error> {
+        match self.swap_path() {
+            Some(path) => swap::read(&path),
+            None => Ok(None),
+        }
+    }
+
+    pub fn remove_swap(&self) {
+        if let Some(path) = self.swap_path() {
+            swap::remove(&path);
+        }
+    }
+
+    ///Runs the save hooks enabled in the config: converting indentation, trimming trailing
+    ///whitespace and making the file end with exactly one newline. Returns where the text
+    ///at `at` ended up, so that the cursor can stay on it.
+    pub fn clean_up(&mut self, at: &Position) -> Position {
+        let mut at = at.clone();
+        if self.is_read_only() || self.is_hex() || self.load_all().is_err() {
+            return at;
+        }
+        let tab_width = self.config.tab_width;
+        let mut first_changed = None;
+        for (y, row) in self.rows.iter_mut().enumerate() {
+            let mut line = row.as_str().to_string();
+            if let Some(style) = self.config.convert_indent {
+                line = convert_indent(&line, style, tab_width);
+            }
+            if self.config.trim_trailing_whitespace {
+                line.truncate(line.trim_end().len());
+            }
+            if line != row.as_str() {
+                let new = Row::from(&line[..]);
+                if y == at.y {
+                    at.x = map_column(row.as_str(), &new, at.x, tab_width);
+                }
+                *row = new;
+                first_changed.get_or_insert(y);
+            }
+        }
+        if self.config.ensure_final_newline {
+            while self.rows.last().is_some_and(|row| row.len() == 0) {
+                self.rows.pop();
+                first_changed.get_or_insert(self.rows.len());
+            }
+            if !self.final_newline {
+                self.final_newline = true;
+                first_changed.get_or_insert(self.rows.len());
+            }
+            if at.y > self.rows.len() {
+                at = Position { x: 0, y: self.rows.len() };
+            }
+        }
+        if let Some(y) = first_changed {
+            self.mark_dirty();
+            self.unhighlight_rows(y);
+        }
+        at
+    }
+
     pub fn save(&mut self) -> Result<usize, Error> {
+        if self.is_listing() {
+            return Err(Error::new(ErrorKind::IsADirectory, "directory listings can't be saved"));
+        }
+        if self.read_only || self.forced_read_only {
+            return Err(Error::new(ErrorKind::PermissionDenied, "buffer is read-only"));
+        }
+        //only part of a large file is in memory
+        self.load_all()?;
         let mut nbytes: usize = 0;
         if let Some(filename) = &self.filename {
-            let mut file = fs::File::create(filename)?;
-            self.hl_opts.file_type = HighlightOptions::set_filetype(filename);
-            for row in &self.rows {
-                nbytes += row.len();
-                file.write_all(row.as_bytes())?;
-                file.write_all(b"\n")?;
+            //encode before touching the file, so an unencodable character doesn't truncate it
+            let bytes = match &self.hex {
+                Some(bytes) => bytes.clone(),
+                None => self.encoding.encode(&self.contents())?,
+            };
+            let bytes = match self.compression {
+                Some(compression) => compression.compress(&bytes)?,
+                None => bytes,
+            };
+            backup::backup(&resolve_symlinks(Path::new(filename)), &self.config)?;
+            write_atomic(Path::new(filename), &bytes)?;
+            nbytes = bytes.len();
+            self.disk_stamp = DiskStamp::read(filename);
+            if !self.is_hex() {
+                self.hl_opts.file_type = HighlightOptions::set_filetype(filename);
             }
 
             self.dirty = false;
@@ -262,29 +1075,39 @@ impl File {
     }
     
     pub fn num_rows(&self) -> usize {
-        self.rows.len()
+        self.len()
     }
     
     pub fn row(&self, index: usize) -> Option<&Row> {
-        self.rows.get(index)
+        self.rows.get(index.checked_sub(self.window_start)?)
     }
     
     pub fn is_empty(&self) -> bool {
-        self.rows.is_empty()
+        self.len() == 0
     }
+    ///Notes that rows changed from `start` on. Changed rows know it themselves, this makes
+    ///`highlight` look at them again, along with the rows after them that may now start
+    ///inside or outside a multiline comment.
     pub fn unhighlight_rows(&mut self, start: usize){
-        let start = start.saturating_sub(1);
-        for row in self.rows.iter_mut().skip(start){
-            row.is_highlighted = false; 
-        }
+        self.hl_valid_until = self.hl_valid_until.min(start);
+    }
+
+    ///Throws away all cached highlighting, for when the rules change.
+    fn rehighlight_all(&mut self) {
+        self.hl_valid_until = 0;
+        self.hl_generation = self.hl_generation.wrapping_add(1);
     }
 
     pub fn insert(&mut self, at: &Position, c: char){
-        if at.y > self.rows.len(){
+        if self.is_read_only() || self.load_all().is_err() || at.y > self.rows.len() {
+            return;
+        }
+        if self.is_hex() {
+            self.overwrite_nibble(at, c);
             return;
         }
 
-        self.dirty = true;
+        self.mark_dirty();
         if c == '\n' {
             self.insert_newline(at);
         } else if at.y == self.rows.len() {
@@ -299,10 +1122,10 @@ impl File {
     }
 
     pub fn delete(&mut self, at: &Position){
-        if at.y > self.rows.len(){
+        if self.is_read_only() || self.is_hex() || self.load_all().is_err() || at.y > self.rows.len() {
             return;
         }
-        self.dirty = true;
+        self.mark_dirty();
         if at.x == self.rows[at.y].len() && at.y + 1 < self.rows.len(){
             //do nothing for now, but the rows should be merged.
             let next_row = self.rows.remove(at.y+1);
@@ -312,6 +1135,28 @@ impl File {
             let row = &mut self.rows[at.y];
             row.delete(at.x);
         }
+        self.unhighlight_rows(at.y);
+    }
+
+    ///Replaces the hex digit under the cursor with `c`, if it is a hex digit.
+    fn overwrite_nibble(&mut self, at: &Position, c: char) {
+        let bytes = match &mut self.hex {
+            Some(bytes) => bytes,
+            None => return,
+        };
+        let offset = at.y * hex::BYTES_PER_ROW + at.x / 2;
+        let (byte, value) = match (bytes.get_mut(offset), c.to_digit(16)) {
+            (Some(byte), Some(value)) => (byte, value as u8),
+            _ => return,
+        };
+        *byte = if at.x.is_multiple_of(2) {
+            (value << 4) | (*byte & 0x0F)
+        } else {
+            (*byte & 0xF0) | value
+        };
+        self.rows[at.y] = hex::dump_row(bytes, at.y);
+        self.unhighlight_rows(at.y);
+        self.mark_dirty();
     }
 
     fn insert_newline(&mut self, at: &Position){
@@ -322,15 +1167,30 @@ impl File {
             self.rows.push(Row::default());
         }
         let current_row = &mut self.rows[at.y];
-        let num_spaces = current_row.get_prefix_len(" ");
+        let indent = match self.config.indent_style {
+            Some(style) => convert_indent(current_row.indent(), style, self.config.tab_width),
+            None => current_row.indent().to_string(),
+        };
         let mut new_row = current_row.split(at.x);
-        new_row.prepend_str(&" ".repeat(num_spaces));
-        
+        new_row.prepend_str(&indent);
 
         self.rows.insert(at.y+1, new_row);
     }
 
     pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position> {
+        if let Some(large) = &self.large {
+            return large.find(query, at, direction);
+        }
+        if let Some(bytes) = &self.hex {
+            let needle = hex::parse_query(query)?;
+            //start after the byte under the cursor when it is on its second digit
+            let from = at.y * hex::BYTES_PER_ROW + at.x.div_ceil(2);
+            let offset = hex::find(bytes, &needle, from, direction)?;
+            return Some(Position {
+                x: (offset % hex::BYTES_PER_ROW) * 2,
+                y: offset / hex::BYTES_PER_ROW,
+            });
+        }
         if at.y >= self.rows.len() {
             return None;
         }
@@ -370,22 +1230,40 @@ impl File {
         None
     }
 
-    ///Highlights selected word in the text, and any highlighting options enabled.
-    pub fn highlight(&mut self, word: &Option<String>, until: Option<usize>){
+    ///Highlights selected word in the text, and any highlighting options enabled, up to
+    ///row `until`. Rows from `from` on are about to be drawn, and get their search matches
+    ///brought up to date.
+    pub fn highlight(&mut self, word: &Option<String>, from: usize, until: Option<usize>){
         let mut start_with_comment = false;
+        //in large file mode only a window of rows is loaded
         let until = if let Some(until) = until {
-            if until.saturating_add(1) < self.rows.len() {
-                until.saturating_add(1)
-            } else {
-                self.rows.len()
-            }
+            until.saturating_add(1).saturating_sub(self.window_start)
         } else {
             self.rows.len()
         };
+        let until = until.min(self.rows.len());
+        if *word != self.hl_word {
+            self.hl_word = word.clone();
+            //a search match never spans rows, so the comment states all still hold
+            self.hl_generation = self.hl_generation.wrapping_add(1);
+        }
+        let generation = self.hl_generation;
 
-        for row in &mut self.rows[..until] {
-            start_with_comment = row.highlight(&self.hl_opts, word, start_with_comment);
+        //rows past the first changed one are only tokenized again if they changed too, or
+        //if they now start in a different state
+        let start = self.hl_valid_until.min(until);
+        let from = from.saturating_sub(self.window_start).min(start);
+        for row in self.rows.range_mut(from, start) {
+            let start_with_comment = row.starts_in_comment();
+            row.highlight(&self.hl_opts, word, start_with_comment, generation);
+        }
+        if let Some(previous) = start.checked_sub(1).and_then(|y| self.rows.get(y)) {
+            start_with_comment = previous.ends_in_comment();
+        }
+        for row in self.rows.range_mut(start, until) {
+            start_with_comment = row.highlight(&self.hl_opts, word, start_with_comment, generation);
         }
+        self.hl_valid_until = self.hl_valid_until.max(until);
     }
 
 }
\ No newline at end of file
@@ -0,0 +1,85 @@
+use crate::editor::SearchDirection;
+use crate::row::Row;
+
+pub const BYTES_PER_ROW: usize = 16;
+//"00000000  " in front of the bytes of every row
+const OFFSET_WIDTH: usize = 10;
+//how much of the file is looked at to decide whether it is binary
+const SNIFF_SIZE: usize = 8000;
+
+///Whether `bytes` look like the contents of a binary file rather than text. Like git, a
+///NUL byte near the start is taken as the sign of a binary file, except in UTF-16 text
+///where NUL bytes are common.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    if bytes.starts_with(&[0xFF, 0xFE]) || bytes.starts_with(&[0xFE, 0xFF]) {
+        return false;
+    }
+    bytes.iter().take(SNIFF_SIZE).any(|&b| b == 0)
+}
+
+///Hex dump of the `index`th row of `bytes`: the offset, the bytes in hex and the bytes
+///as ASCII, where unprintable bytes are shown as `.`.
+pub fn dump_row(bytes: &[u8], index: usize) -> Row {
+    let start = index * BYTES_PER_ROW;
+    let chunk = &bytes[start.min(bytes.len())..(start + BYTES_PER_ROW).min(bytes.len())];
+    let mut line = format!("{:08x}  ", start);
+    for column in 0..BYTES_PER_ROW {
+        match chunk.get(column) {
+            Some(byte) => line.push_str(&format!("{:02x} ", byte)),
+            None => line.push_str("   "),
+        }
+    }
+    line.push_str(" |");
+    for &byte in chunk {
+        line.push(if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' });
+    }
+    line.push('|');
+    Row::from(&line[..])
+}
+
+pub fn dump(bytes: &[u8]) -> Vec<Row> {
+    (0..row_count(bytes)).map(|index| dump_row(bytes, index)).collect()
+}
+
+pub fn row_count(bytes: &[u8]) -> usize {
+    bytes.len().div_ceil(BYTES_PER_ROW)
+}
+
+///Screen column of the `nibble`th hex digit in a dumped row.
+pub fn column(nibble: usize) -> usize {
+    OFFSET_WIDTH + (nibble / 2) * 3 + nibble % 2
+}
+
+///The hex digit drawn at or just before screen column `column` of a dumped row, the
+///inverse of `column`.
+pub fn nibble(column: usize) -> usize {
+    let column = column.saturating_sub(OFFSET_WIDTH);
+    (column / 3) * 2 + (column % 3).min(1)
+}
+
+///Parses a search query made of hex digits, e.g. `de ad be ef` or `deadbeef`.
+pub fn parse_query(query: &str) -> Option<Vec<u8>> {
+    let digits: String = query.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(digits.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+///Offset of the first occurrence of `needle` in `bytes` starting at `from`, or of the last
+///one starting before `from` when searching backward.
+pub fn find(bytes: &[u8], needle: &[u8], from: usize, direction: SearchDirection) -> Option<usize> {
+    if needle.is_empty() || needle.len() > bytes.len() {
+        return None;
+    }
+    let last = bytes.len() - needle.len();
+    let matches = |offset: &usize| bytes[*offset..*offset + needle.len()] == *needle;
+    if direction == SearchDirection::Forward {
+        (from..=last).find(matches)
+    } else {
+        (0..from.min(last + 1)).rev().find(matches)
+    }
+}
@@ -12,10 +12,12 @@ pub enum Highlight {
     Keyword2,
     Number,
     Match,
+    ///Whitespace and invisible characters, in visible whitespace mode
+    Whitespace,
 }
 
 impl Highlight {
-    pub fn to_true_color(self) -> impl color::Color {
+    pub fn to_true_color(self) -> color::Rgb {
         match self {
             //these values are taken from the konsole breathe color palette
             Highlight::Number => color::Rgb(237, 21, 21),
@@ -25,6 +27,7 @@ impl Highlight {
             Highlight::Comment | Highlight::MlComment => color::Rgb(61, 174, 233),
             Highlight::Keyword1 => color::Rgb(155, 89, 182),
             Highlight::Keyword2 => color::Rgb(253, 188, 75),
+            Highlight::Whitespace => color::Rgb(99, 104, 109),
             _ => color::Rgb(23, 168, 139),
         }
     }
@@ -0,0 +1,182 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Read};
+use std::os::unix::fs::FileExt;
+
+use crate::editor::SearchDirection;
+use crate::encoding::Encoding;
+use crate::file::LineEnding;
+use crate::hex;
+use crate::row::Row;
+use crate::screen::Position;
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+//number of lines read at once while searching
+const SEARCH_BLOCK: usize = 4096;
+
+///A file too big to be read into rows all at once. Only the offsets at which lines start
+///are kept in memory, and lines are read from disk when they are needed.
+pub struct LargeFile {
+    file: fs::File,
+    line_starts: Vec<u64>,
+    size: u64,
+    encoding: Encoding,
+    line_ending: LineEnding,
+    final_newline: bool,
+}
+
+impl LargeFile {
+    ///Indexes the lines of `filename` by reading it in chunks. The encoding is detected
+    ///if not given. Only encodings in which a newline is a single `\n` byte are supported.
+    pub fn open(filename: &str, encoding: Option<Encoding>) -> Result<Self, Error> {
+        let mut file = fs::File::open(filename)?;
+        let size = file.metadata()?.len();
+        let mut line_starts = Vec::new();
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let mut offset: u64 = 0;
+        let mut encoding = encoding;
+        let mut crlf = 0;
+        let mut last = 0;
+        loop {
+            let n = file.read(&mut chunk)?;
+            if n == 0 {
+                break;
+            }
+            if encoding.is_none() {
+                encoding = Some(sniff_encoding(&chunk[..n]));
+            }
+            if let Some(Encoding::Utf16Le | Encoding::Utf16Be) = encoding {
+                return Err(Error::new(ErrorKind::Unsupported,
+                    "large file mode doesn't support utf-16"));
+            }
+            if offset == 0 && hex::is_binary(&chunk[..n]) {
+                return Err(Error::new(ErrorKind::Unsupported,
+                    "large file mode doesn't support binary files"));
+            }
+            for (index, &byte) in chunk[..n].iter().enumerate() {
+                if byte == b'\n' {
+                    if last == b'\r' {
+                        crlf += 1;
+                    }
+                    line_starts.push(offset + index as u64 + 1);
+                }
+                last = byte;
+            }
+            offset += n as u64;
+        }
+
+        let final_newline = size == 0 || last == b'\n';
+        let newlines = line_starts.len();
+        //every newline starts a line, except a final one, and the first line starts at 0
+        if final_newline {
+            line_starts.pop();
+        }
+        if size > 0 {
+            line_starts.insert(0, 0);
+        }
+        let line_ending = if crlf * 2 > newlines {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        };
+
+        Ok(Self {
+            file,
+            line_starts,
+            size,
+            encoding: encoding.unwrap_or_default(),
+            line_ending,
+            final_newline,
+        })
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    pub fn has_final_newline(&self) -> bool {
+        self.final_newline
+    }
+
+    ///Reads lines `start..end` from disk, without their line terminators.
+    pub fn read_lines(&self, start: usize, end: usize) -> Result<Vec<String>, Error> {
+        let end = end.min(self.line_count());
+        if start >= end {
+            return Ok(Vec::new());
+        }
+        let from = self.line_starts[start];
+        let to = self.line_starts.get(end).copied().unwrap_or(self.size);
+        let mut bytes = vec![0; (to - from) as usize];
+        self.file.read_exact_at(&mut bytes, from)?;
+
+        let mut lines = Vec::with_capacity(end - start);
+        for line in bytes.split(|&b| b == b'\n').take(end - start) {
+            let line = if self.line_ending == LineEnding::CrLf {
+                line.strip_suffix(b"\r").unwrap_or(line)
+            } else {
+                line
+            };
+            lines.push(self.encoding.decode(line)
+                .unwrap_or_else(|_| String::from_utf8_lossy(line).into_owned()));
+        }
+        Ok(lines)
+    }
+
+    ///Same as `File::find`, reading the file from disk a block of lines at a time.
+    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position> {
+        if at.y >= self.line_count() || query.is_empty() {
+            return None;
+        }
+        if direction == SearchDirection::Forward {
+            let mut start = at.y;
+            while start < self.line_count() {
+                let lines = self.read_lines(start, start + SEARCH_BLOCK).ok()?;
+                for (index, line) in lines.iter().enumerate() {
+                    let y = start + index;
+                    let x = if y == at.y { at.x } else { 0 };
+                    //building a row is costly, only do it for lines that can match
+                    if line.contains(query) {
+                        if let Some(x) = Row::from(&line[..]).find(query, x, direction) {
+                            return Some(Position { x, y });
+                        }
+                    }
+                }
+                start += SEARCH_BLOCK;
+            }
+        } else {
+            let mut end = at.y + 1;
+            while end > 0 {
+                let start = end.saturating_sub(SEARCH_BLOCK);
+                let lines = self.read_lines(start, end).ok()?;
+                for (index, line) in lines.iter().enumerate().rev() {
+                    let y = start + index;
+                    if line.contains(query) {
+                        let row = Row::from(&line[..]);
+                        let x = if y == at.y { at.x } else { row.len() };
+                        if let Some(x) = row.find(query, x, direction) {
+                            return Some(Position { x, y });
+                        }
+                    }
+                }
+                end = start;
+            }
+        }
+        None
+    }
+}
+
+///Guesses the encoding from the first chunk of the file. A UTF-8 sequence cut in half at
+///the end of the chunk doesn't count against UTF-8.
+fn sniff_encoding(chunk: &[u8]) -> Encoding {
+    match std::str::from_utf8(chunk) {
+        Err(e) if e.error_len().is_none() => Encoding::Utf8,
+        _ => Encoding::detect(chunk),
+    }
+}
@@ -0,0 +1,40 @@
+use std::fs;
+use std::io::Error;
+use std::path::{Path, PathBuf};
+
+use crate::row::Row;
+
+///Entries to show in the listing of `dir`: its parent if it has one, then the directories
+///and then the other files in it, each sorted by name.
+pub fn read(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        //follows symlinks, so a link to a directory can be descended into
+        if path.is_dir() {
+            dirs.push(path);
+        } else {
+            files.push(path);
+        }
+    }
+    dirs.sort();
+    files.sort();
+
+    let mut entries: Vec<PathBuf> = dir.parent().map(Path::to_path_buf).into_iter().collect();
+    entries.append(&mut dirs);
+    entries.append(&mut files);
+    Ok(entries)
+}
+
+///Row showing `entry` in the listing of `dir`: its name, followed by a `/` for directories.
+pub fn row(dir: &Path, entry: &Path) -> Row {
+    if dir.parent() == Some(entry) {
+        return Row::from("../");
+    }
+    let mut name = entry.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    if entry.is_dir() {
+        name.push('/');
+    }
+    Row::from(&name[..])
+}
@@ -3,19 +3,52 @@ mod row;
 mod highlight;
 mod file;
 mod screen;
+mod encoding;
+mod config;
+mod backup;
+mod swap;
+mod diff;
+mod large;
+mod hex;
+mod compression;
+mod listing;
+mod editorconfig;
+mod rope;
+mod event;
 
 use editor::Editor;
 use file::File;
 
+const USAGE: &str = "usage: hecto [--readonly | -R] [--] [file]";
+
 fn main() -> Result<(), std::io::Error> {
-    let args : Vec<String> = std::env::args().collect();
-    let file = if let Some(filename) = args.get(1) {
+    let mut read_only = false;
+    let mut filename = None;
+    let mut options = true;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            //everything after this is a file name, even if it starts with a -
+            "--" if options => options = false,
+            "--readonly" | "-R" if options => read_only = true,
+            option if options && option.starts_with('-') && option != "-" => {
+                eprintln!("hecto: unknown option {}\n{}", option, USAGE);
+                std::process::exit(2);
+            },
+            _ if filename.is_none() => filename = Some(arg),
+            _ => {
+                eprintln!("hecto: only one file can be opened\n{}", USAGE);
+                std::process::exit(2);
+            },
+        }
+    }
+    let mut file = if let Some(filename) = &filename {
         File::open(filename)?
     } else {
         File::default()
     };
+    if read_only {
+        file.set_read_only(true);
+    }
 
-    Editor::new(file).unwrap().run();
-
-    Ok(())
+    Editor::new(file)?.run()
 }
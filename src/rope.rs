@@ -0,0 +1,471 @@
+use std::mem;
+use std::ops::{Index, IndexMut};
+
+use crate::row::Row;
+
+//most rows a leaf, or children an inner node, holds before it is split in two
+const MAX_WIDTH: usize = 64;
+//nodes narrower than this after a removal are merged with a neighbour
+const MIN_WIDTH: usize = MAX_WIDTH / 4;
+//how full nodes are made when building a rope from scratch, leaving room to grow
+const FILL_WIDTH: usize = MAX_WIDTH * 3 / 4;
+
+///The rows of a buffer, held in a rope: a balanced tree whose leaves are runs of rows and
+///whose inner nodes know how many rows are below them. Finding, inserting and removing a
+///row takes time logarithmic in the number of rows, where a `Vec` shifts every row after
+///the change.
+#[derive(Default)]
+pub struct Rope {
+    root: Node,
+}
+
+enum Node {
+    Leaf(Vec<Row>),
+    Inner { children: Vec<Node>, len: usize },
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Node::Leaf(Vec::new())
+    }
+}
+
+impl Node {
+    ///Number of rows below this node.
+    fn len(&self) -> usize {
+        match self {
+            Node::Leaf(rows) => rows.len(),
+            Node::Inner { len, .. } => *len,
+        }
+    }
+
+    ///Number of rows or children held directly by this node.
+    fn width(&self) -> usize {
+        match self {
+            Node::Leaf(rows) => rows.len(),
+            Node::Inner { children, .. } => children.len(),
+        }
+    }
+
+    ///The child holding row `index`, and the index of that row within the child.
+    fn child_index(children: &[Node], mut index: usize) -> Option<(usize, usize)> {
+        for (i, child) in children.iter().enumerate() {
+            if index < child.len() {
+                return Some((i, index));
+            }
+            index -= child.len();
+        }
+        None
+    }
+
+    fn get(&self, index: usize) -> Option<&Row> {
+        match self {
+            Node::Leaf(rows) => rows.get(index),
+            Node::Inner { children, .. } => {
+                let (i, index) = Self::child_index(children, index)?;
+                children[i].get(index)
+            },
+        }
+    }
+
+    fn get_mut(&mut self, index: usize) -> Option<&mut Row> {
+        match self {
+            Node::Leaf(rows) => rows.get_mut(index),
+            Node::Inner { children, .. } => {
+                let (i, index) = Self::child_index(children, index)?;
+                children[i].get_mut(index)
+            },
+        }
+    }
+
+    ///Inserts `row` at `index`, which is at most `self.len()`. Returns the right half of
+    ///this node if it grew too wide and had to be split.
+    fn insert(&mut self, index: usize, row: Row) -> Option<Node> {
+        match self {
+            Node::Leaf(rows) => rows.insert(index, row),
+            Node::Inner { children, len } => {
+                //past the end goes into the last child
+                let (i, index) = Self::child_index(children, index).unwrap_or_else(|| {
+                    let last = children.len() - 1;
+                    (last, children[last].len())
+                });
+                *len += 1;
+                if let Some(right) = children[i].insert(index, row) {
+                    children.insert(i + 1, right);
+                }
+            },
+        }
+        if self.width() > MAX_WIDTH {
+            Some(self.split_off(self.width() / 2))
+        } else {
+            None
+        }
+    }
+
+    ///Removes row `index`, which is less than `self.len()`, merging children that become
+    ///too narrow with their neighbours.
+    fn remove(&mut self, index: usize) -> Row {
+        match self {
+            Node::Leaf(rows) => rows.remove(index),
+            Node::Inner { children, len } => {
+                let (i, index) = Self::child_index(children, index).expect("row index out of range");
+                let row = children[i].remove(index);
+                *len -= 1;
+                if children[i].width() < MIN_WIDTH && children.len() > 1 {
+                    //merge with the previous child, or the next one for the first child
+                    let i = i.max(1);
+                    let right = children.remove(i);
+                    children[i - 1].append(right);
+                    if children[i - 1].width() > MAX_WIDTH {
+                        let half = children[i - 1].width() / 2;
+                        let right = children[i - 1].split_off(half);
+                        children.insert(i, right);
+                    }
+                }
+                row
+            },
+        }
+    }
+
+    ///Moves the rows or children of `other`, a node at the same depth, to the end of this one.
+    fn append(&mut self, other: Node) {
+        match (self, other) {
+            (Node::Leaf(rows), Node::Leaf(mut other)) => rows.append(&mut other),
+            (Node::Inner { children, len }, Node::Inner { children: mut other, len: other_len }) => {
+                children.append(&mut other);
+                *len += other_len;
+            },
+            _ => unreachable!("nodes of a rope are all at the same depth"),
+        }
+    }
+
+    ///Splits off the rows or children from `at` on into a new node.
+    fn split_off(&mut self, at: usize) -> Node {
+        match self {
+            Node::Leaf(rows) => Node::Leaf(rows.split_off(at)),
+            Node::Inner { children, len } => {
+                let children = children.split_off(at);
+                let right_len = children.iter().map(Node::len).sum();
+                *len -= right_len;
+                Node::Inner { children, len: right_len }
+            },
+        }
+    }
+
+    ///Collects the runs of rows in `start..end` of this node.
+    fn slices<'a>(&'a self, start: usize, end: usize, out: &mut Vec<&'a [Row]>) {
+        match self {
+            Node::Leaf(rows) => out.push(&rows[start.min(rows.len())..end.min(rows.len())]),
+            Node::Inner { children, .. } => {
+                let mut offset = 0;
+                for child in children {
+                    let len = child.len();
+                    if start < offset + len && end > offset {
+                        child.slices(start.saturating_sub(offset), (end - offset).min(len), out);
+                    }
+                    offset += len;
+                }
+            },
+        }
+    }
+
+    fn slices_mut<'a>(&'a mut self, start: usize, end: usize, out: &mut Vec<&'a mut [Row]>) {
+        match self {
+            Node::Leaf(rows) => {
+                let len = rows.len();
+                out.push(&mut rows[start.min(len)..end.min(len)]);
+            },
+            Node::Inner { children, .. } => {
+                let mut offset = 0;
+                for child in children {
+                    let len = child.len();
+                    if start < offset + len && end > offset {
+                        child.slices_mut(start.saturating_sub(offset), (end - offset).min(len), out);
+                    }
+                    offset += len;
+                }
+            },
+        }
+    }
+}
+
+impl Rope {
+    pub fn len(&self) -> usize {
+        self.root.len()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Row> {
+        self.root.get(index)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Row> {
+        self.root.get_mut(index)
+    }
+
+    pub fn last(&self) -> Option<&Row> {
+        self.get(self.len().checked_sub(1)?)
+    }
+
+    ///Inserts `row` at `index`, shifting the rows after it down. Panics if `index` is past
+    ///the end, like `Vec::insert`.
+    pub fn insert(&mut self, index: usize, row: Row) {
+        assert!(index <= self.len(), "row index out of range");
+        if let Some(right) = self.root.insert(index, row) {
+            let left = mem::take(&mut self.root);
+            self.root = Node::Inner { len: left.len() + right.len(), children: vec![left, right] };
+        }
+    }
+
+    pub fn push(&mut self, row: Row) {
+        self.insert(self.len(), row);
+    }
+
+    ///Removes and returns the row at `index`, shifting the rows after it up. Panics if
+    ///there is no such row, like `Vec::remove`.
+    pub fn remove(&mut self, index: usize) -> Row {
+        assert!(index < self.len(), "row index out of range");
+        let row = self.root.remove(index);
+        //the tree gets shallower when the root is left with a single child
+        while let Node::Inner { children, .. } = &mut self.root {
+            if children.len() != 1 {
+                break;
+            }
+            self.root = children.pop().unwrap_or_default();
+        }
+        row
+    }
+
+    pub fn pop(&mut self) -> Option<Row> {
+        let last = self.len().checked_sub(1)?;
+        Some(self.remove(last))
+    }
+
+    ///Iterates over rows `start..end`, clamped to the rows there are.
+    pub fn range(&self, start: usize, end: usize) -> impl Iterator<Item = &Row> {
+        let mut slices = Vec::new();
+        let end = end.min(self.len());
+        self.root.slices(start.min(end), end, &mut slices);
+        slices.into_iter().flatten()
+    }
+
+    pub fn range_mut(&mut self, start: usize, end: usize) -> impl Iterator<Item = &mut Row> {
+        let mut slices = Vec::new();
+        let end = end.min(self.len());
+        self.root.slices_mut(start.min(end), end, &mut slices);
+        slices.into_iter().flatten()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Row> {
+        self.range(0, self.len())
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Row> {
+        self.range_mut(0, self.len())
+    }
+}
+
+impl From<Vec<Row>> for Rope {
+    ///Builds the tree bottom up, which is quicker than inserting the rows one by one.
+    fn from(rows: Vec<Row>) -> Self {
+        let mut rows = rows.into_iter().peekable();
+        let mut nodes = Vec::new();
+        while rows.peek().is_some() {
+            nodes.push(Node::Leaf(rows.by_ref().take(FILL_WIDTH).collect()));
+        }
+        while nodes.len() > 1 {
+            let mut level = nodes.into_iter().peekable();
+            nodes = Vec::new();
+            while level.peek().is_some() {
+                let children: Vec<Node> = level.by_ref().take(FILL_WIDTH).collect();
+                let len = children.iter().map(Node::len).sum();
+                nodes.push(Node::Inner { children, len });
+            }
+        }
+        Self { root: nodes.pop().unwrap_or_default() }
+    }
+}
+
+impl FromIterator<Row> for Rope {
+    fn from_iter<I: IntoIterator<Item = Row>>(rows: I) -> Self {
+        Self::from(rows.into_iter().collect::<Vec<Row>>())
+    }
+}
+
+impl Index<usize> for Rope {
+    type Output = Row;
+
+    fn index(&self, index: usize) -> &Row {
+        self.get(index).expect("row index out of range")
+    }
+}
+
+impl IndexMut<usize> for Rope {
+    fn index_mut(&mut self, index: usize) -> &mut Row {
+        self.get_mut(index).expect("row index out of range")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    ///Small xorshift generator, so that the tests are the same on every run.
+    struct Random(u64);
+
+    impl Random {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    fn row(n: usize) -> Row {
+        Row::from(&n.to_string()[..])
+    }
+
+    fn texts<'a>(rows: impl Iterator<Item = &'a Row>) -> Vec<String> {
+        rows.map(|row| row.as_str().to_string()).collect()
+    }
+
+    ///Checks the lengths and widths kept in `node`, and returns its depth, which is the
+    ///same along every path down from it.
+    fn check_node(node: &Node, root: bool) -> usize {
+        assert!(node.width() <= MAX_WIDTH, "node is too wide");
+        match node {
+            Node::Leaf(_) => 1,
+            Node::Inner { children, len } => {
+                assert!(!children.is_empty(), "inner node without children");
+                assert!(!root || children.len() > 1, "root with a single child wasn't collapsed");
+                assert_eq!(*len, children.iter().map(Node::len).sum::<usize>());
+                let depths: Vec<usize> = children.iter().map(|child| check_node(child, false)).collect();
+                assert!(depths.windows(2).all(|pair| pair[0] == pair[1]), "leaves at different depths");
+                depths[0] + 1
+            },
+        }
+    }
+
+    ///Checks `rope` against `reference`, and returns the depth of the rope.
+    fn check(rope: &Rope, reference: &[String]) -> usize {
+        assert_eq!(rope.len(), reference.len());
+        assert_eq!(texts(rope.iter()), reference);
+        for (index, text) in reference.iter().enumerate().step_by(7) {
+            assert_eq!(rope[index].as_str(), text);
+        }
+        assert!(rope.get(reference.len()).is_none());
+        assert_eq!(rope.last().map(|row| row.as_str().to_string()).as_ref(), reference.last());
+        check_node(&rope.root, true)
+    }
+
+    #[test]
+    fn insert_and_remove_match_a_vec() {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        let mut rope = Rope::default();
+        let mut reference = Vec::new();
+        for n in 0..5000 {
+            let index = random.below(reference.len() + 1);
+            rope.insert(index, row(n));
+            reference.insert(index, n.to_string());
+            if n % 500 == 0 {
+                check(&rope, &reference);
+            }
+        }
+        assert!(check(&rope, &reference) >= 3, "the tree should have grown a few levels");
+
+        while !reference.is_empty() {
+            let index = random.below(reference.len());
+            assert_eq!(rope.remove(index).as_str(), reference.remove(index));
+            if reference.len() % 250 == 0 {
+                check(&rope, &reference);
+            }
+        }
+        //drained, the root is a single empty leaf again
+        assert_eq!(check(&rope, &reference), 1);
+        assert!(rope.pop().is_none());
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let mut rope = Rope::default();
+        let mut reference = Vec::new();
+        for n in 0..1000 {
+            rope.push(row(n));
+            reference.push(n.to_string());
+        }
+        check(&rope, &reference);
+        while let Some(text) = reference.pop() {
+            assert_eq!(rope.pop().map(|row| row.as_str().to_string()), Some(text));
+        }
+        assert_eq!(check(&rope, &reference), 1);
+    }
+
+    #[test]
+    fn removing_from_the_front_collapses_the_root() {
+        let mut rope: Rope = (0..MAX_WIDTH * MAX_WIDTH).map(row).collect();
+        let mut reference: Vec<String> = (0..MAX_WIDTH * MAX_WIDTH).map(|n| n.to_string()).collect();
+        let mut depth = check(&rope, &reference);
+        assert!(depth > 2);
+        while !reference.is_empty() {
+            rope.remove(0);
+            reference.remove(0);
+            let new_depth = check_node(&rope.root, true);
+            assert!(new_depth <= depth, "the tree grew while shrinking");
+            depth = new_depth;
+        }
+        assert_eq!(check(&rope, &reference), 1);
+    }
+
+    #[test]
+    fn from_vec() {
+        for len in [0, 1, FILL_WIDTH, FILL_WIDTH + 1, MAX_WIDTH * FILL_WIDTH + 3] {
+            let rope = Rope::from((0..len).map(row).collect::<Vec<Row>>());
+            let reference: Vec<String> = (0..len).map(|n| n.to_string()).collect();
+            check(&rope, &reference);
+        }
+    }
+
+    #[test]
+    fn ranges() {
+        let len = 1000;
+        let rope: Rope = (0..len).map(row).collect();
+        let reference: Vec<String> = (0..len).map(|n| n.to_string()).collect();
+        let mut random = Random(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..500 {
+            let start = random.below(len + 1);
+            let end = start + random.below(len + 1 - start);
+            assert_eq!(texts(rope.range(start, end)), &reference[start..end]);
+        }
+        //clamped to the rows there are
+        assert_eq!(texts(rope.range(990, 2000)), &reference[990..]);
+        assert!(rope.range(2000, 3000).next().is_none());
+        assert!(rope.range(500, 400).next().is_none());
+    }
+
+    #[test]
+    fn range_mut_changes_only_its_rows() {
+        let mut rope: Rope = (0..500).map(row).collect();
+        let mut reference: Vec<String> = (0..500).map(|n| n.to_string()).collect();
+        for row in rope.range_mut(100, 300) {
+            row.insert(0, 'x');
+        }
+        for text in &mut reference[100..300] {
+            text.insert(0, 'x');
+        }
+        check(&rope, &reference);
+    }
+
+    #[test]
+    #[should_panic(expected = "row index out of range")]
+    fn insert_past_the_end_panics() {
+        let mut rope: Rope = (0..10).map(row).collect();
+        rope.insert(11, row(11));
+    }
+
+    #[test]
+    #[should_panic(expected = "row index out of range")]
+    fn remove_past_the_end_panics() {
+        let mut rope: Rope = (0..10).map(row).collect();
+        rope.remove(10);
+    }
+}
@@ -1,14 +1,46 @@
 use unicode_segmentation::UnicodeSegmentation;
 use crate::{highlight::Highlight, editor::SearchDirection, file::HighlightOptions};
-use std::{cmp};
+use crate::screen::{self, Cell, Style};
+use std::{cmp, ops::Range};
 use termion::color;
 
 const HECTO_TAB_SPACE: &str = " ";
+//glyphs of visible whitespace mode
+const SPACE_GLYPH: &str = "·";
+const TAB_GLYPH: &str = "→";
+const NBSP_GLYPH: &str = "␣";
+const ZERO_WIDTH_GLYPH: &str = "¦";
+///What is drawn behind the text of a row: a background for all of it, e.g. for the
+///cursor's row, and rulers, which go over it.
+pub struct Backgrounds<'a> {
+    pub line: Option<color::Rgb>,
+    ///columns the rulers are at, counted from 1
+    pub rulers: &'a [usize],
+    pub ruler: color::Rgb,
+}
+
+impl Backgrounds<'_> {
+    ///Background of something taking up screen columns `columns` of the row. A ruler on
+    ///any of them colors all of it, so that a wide character is never split.
+    pub fn at(&self, columns: Range<usize>) -> Option<color::Rgb> {
+        if self.rulers.iter().any(|&ruler| columns.contains(&(ruler - 1))) {
+            Some(self.ruler)
+        } else {
+            self.line
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct Row {
     pub string: String,
     highlight: Vec<Highlight>,
     pub is_highlighted: bool,
+    //search match generation of the file the cached highlighting was computed in
+    hl_generation: u64,
+    //multiline comment state the cached highlighting was computed from, and ends in
+    starts_in_comment: bool,
+    ends_in_comment: bool,
     len: usize,
 }
 
@@ -18,6 +50,9 @@ impl From<&str> for Row {
             string: String::from(slice),
             highlight: Vec::new(),
             is_highlighted: false,
+            hl_generation: 0,
+            starts_in_comment: false,
+            ends_in_comment: false,
             len: slice.graphemes(true).count(),
         }
     }
@@ -27,95 +62,183 @@ fn is_separator(c: char) -> bool{
    c.is_control() || c == '\r' || c == '\n' || c.is_whitespace() || ";{} <>()[],.+-/*=-%".contains(c)
 }
 
+///Glyph showing `grapheme` in visible whitespace mode, if it is whitespace or invisible.
+///Spaces other than the plain one, such as non-breaking spaces, all get the same glyph.
+fn whitespace_glyph(grapheme: &str) -> Option<&'static str> {
+    match grapheme {
+        " " => Some(SPACE_GLYPH),
+        "\t" => Some(TAB_GLYPH),
+        "\u{200b}" | "\u{200c}" | "\u{200d}" | "\u{2060}" | "\u{feff}" => Some(ZERO_WIDTH_GLYPH),
+        _ if grapheme.chars().all(char::is_whitespace) => Some(NBSP_GLYPH),
+        _ => None,
+    }
+}
+
+///Screen columns `grapheme` takes when drawn at `column`: a tab reaches to the next tab stop.
+fn grapheme_width(grapheme: &str, column: usize, tab_width: usize) -> usize {
+    if grapheme == "\t" {
+        let tab_width = tab_width.max(1);
+        tab_width - column % tab_width
+    } else {
+        screen::display_width(grapheme)
+    }
+}
+
 impl Row {
     pub fn len(&self) -> usize {
         self.len
     }
 
-    pub fn as_bytes(&self) -> &[u8] {
-        self.string.as_bytes()
+    pub fn as_str(&self) -> &str {
+        &self.string
     }
 
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let start = cmp::min(start, end);
-        let end = cmp::min(end, self.string.len());
-        let mut result = String::new();
-        let mut current_highlighting = &Highlight::None;
-        for (index,grapheme) in self.string[..]
-            .graphemes(true)
-            .enumerate()
-            .skip(start)
-            .take(end-start)
-        {
-            if let Some(_) = grapheme.chars().next() {
-                let highlighting_type = self.highlight
-                    .get(index)
-                    .unwrap_or(&Highlight::None);
-                if highlighting_type != current_highlighting {
-                    current_highlighting = highlighting_type;
-                    let start_highlight =
-                        format!("{}", termion::color::Fg(highlighting_type.to_true_color()));
-                    result.push_str(&start_highlight[..]);
-                } 
-                
-                if grapheme == "\t" {
-                    result.push_str(HECTO_TAB_SPACE);
-                } else {
-                    result.push_str(grapheme);
+    ///Cells showing screen columns `start..end` of the row, colored by their highlighting
+    ///and on `backgrounds`, with tabs stopping every `tab_width` columns. With
+    ///`show_whitespace`, whitespace and invisible characters get glyphs, and whitespace at
+    ///the end of the row stands out.
+    pub fn render(&self, start: usize, end: usize, tab_width: usize, show_whitespace: bool,
+        backgrounds: &Backgrounds) -> Vec<Cell> {
+        let mut cells = Vec::new();
+        let mut column = 0;
+        let trailing = self.string.trim_end().graphemes(true).count();
+        for (index, grapheme) in self.string.graphemes(true).enumerate() {
+            if column >= end {
+                break;
+            }
+            let width = grapheme_width(grapheme, column, tab_width);
+            let glyph = if show_whitespace { whitespace_glyph(grapheme) } else { None };
+            let highlighting_type = match self.highlight.get(index) {
+                Some(Highlight::Match) => Highlight::Match,
+                _ if glyph.is_some() => Highlight::Whitespace,
+                highlighting_type => *highlighting_type.unwrap_or(&Highlight::None),
+            };
+            let style = Style {
+                fg: Some(highlighting_type.to_true_color()),
+                inverted: glyph.is_some() && index >= trailing,
+                ..Style::default()
+            };
+            //blank columns are cells of their own, each on its own background
+            let blank = |x: usize| Style { bg: backgrounds.at(x..x + 1), ..style };
+            let inside = column >= start && column + width <= end;
+            if let Some(glyph) = glyph.filter(|_| inside) {
+                cells.push(Cell { symbol: glyph.to_string(), style: blank(column) });
+                for x in column + 1..column + width {
+                    cells.push(Cell { symbol: HECTO_TAB_SPACE.to_string(), style: blank(x) });
+                }
+            } else if grapheme != "\t" && inside && !screen::is_invisible(grapheme) {
+                let style = Style { bg: backgrounds.at(column..column + width), ..style };
+                cells.push(Cell { symbol: grapheme.to_string(), style });
+                for _ in 1..width {
+                    cells.push(Cell { symbol: String::new(), style });
+                }
+            } else {
+                //tabs, invisible graphemes, and wide graphemes cut by an edge of the view show
+                //as blank columns
+                for x in cmp::max(column, start)..cmp::min(column + width, end) {
+                    cells.push(Cell { symbol: HECTO_TAB_SPACE.to_string(), style: blank(x) });
                 }
             }
+            column += width;
         }
-        let end_highlight = format!("{}", termion::color::Fg(color::Reset));
-        result.push_str(&end_highlight[..]);
-        result
+        cells
     }
 
-    pub fn insert(&mut self, at: usize, c: char){
-        if at >= self.len() {
-            self.string.push(c);
-            self.len += 1;
-            return;
+    ///Screen column at which the `at`th grapheme is drawn.
+    pub fn column(&self, at: usize, tab_width: usize) -> usize {
+        self.string
+            .graphemes(true)
+            .take(at)
+            .fold(0, |column, grapheme| column + grapheme_width(grapheme, column, tab_width))
+    }
+
+    ///Index of the grapheme drawn over screen column `column`, or the length of the row
+    ///when it is past the end.
+    pub fn index_at(&self, column: usize, tab_width: usize) -> usize {
+        let mut end = 0;
+        for (index, grapheme) in self.string.graphemes(true).enumerate() {
+            end += grapheme_width(grapheme, end, tab_width);
+            if column < end {
+                return index;
+            }
         }
-        let mut result = String::new();
-        let mut length = 0;
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate(){
-            length += 1;
-            if index == at {
-                length += 1;
-                result.push(c);
+        self.len
+    }
+
+    ///Where the lines of the row start when it is wrapped at `width` screen columns: at 0,
+    ///and then wherever the line before is full. With `words`, a line ends after the last
+    ///separator that fits on it, so that words are kept whole when they can be.
+    pub fn wrap(&self, width: usize, words: bool, tab_width: usize) -> Vec<usize> {
+        let graphemes: Vec<&str> = self.string.graphemes(true).collect();
+        //the column every grapheme starts at, and the one after the last
+        let mut columns = vec![0];
+        for grapheme in &graphemes {
+            let column = columns[columns.len() - 1];
+            columns.push(column + grapheme_width(grapheme, column, tab_width));
+        }
+        let mut starts = vec![0];
+        let mut start = 0;
+        loop {
+            //a line holds at least one grapheme, however wide
+            let limit = columns[start] + width.max(1);
+            let mut end = start + 1;
+            while end < self.len && columns[end + 1] <= limit {
+                end += 1;
+            }
+            if end >= self.len {
+                break;
             }
-            result.push_str(grapheme);
+            if words {
+                let separator = (start..end).rev()
+                    .find(|&i| graphemes[i].chars().next().is_some_and(is_separator));
+                if let Some(i) = separator {
+                    end = i + 1;
+                }
+            }
+            starts.push(end);
+            start = end;
         }
+        starts
+    }
+
+    ///Byte offset of the `at`th grapheme, or the length of the string past the last one.
+    fn byte_index(&self, at: usize) -> usize {
+        self.string
+            .grapheme_indices(true)
+            .nth(at)
+            .map_or(self.string.len(), |(index, _)| index)
+    }
 
-        self.len = length;
-        self.string = result;
+    pub fn insert(&mut self, at: usize, c: char){
+        let index = self.byte_index(at);
+        self.string.insert(index, c);
+        self.is_highlighted = false;
+        //a combining character joins the grapheme before it rather than adding one
+        self.len = self.string.graphemes(true).count();
     }
 
     pub fn delete(&mut self, at: usize){
         if at >= self.len() {
             return;
         }
-        let mut result = String::new();
-        let mut length = 0;
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate(){
-            if index != at {
-                length += 1;
-                result.push_str(grapheme);
-            }
-        }
-
-        self.len = length;
-        self.string = result;
+        let start = self.byte_index(at);
+        let end = self.byte_index(at + 1);
+        self.string.replace_range(start..end, "");
+        //the graphemes on either side may join up into one
+        self.len = self.string.graphemes(true).count();
+        self.is_highlighted = false;
     }
 
     pub fn append(&mut self, new: &Self){
-        self.string = format!("{}{}", self.string, new.string);
-        self.len += new.len;
+        self.string.push_str(&new.string);
+        self.len = self.string.graphemes(true).count();
+        self.is_highlighted = false;
     }
 
     pub fn prepend_str(&mut self, s: &str){
-        self.string = format!("{}{}", s, self.string);
-        self.len += s.len();
+        self.string.insert_str(0, s);
+        self.len = self.string.graphemes(true).count();
+        self.is_highlighted = false;
     }
 
     pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
@@ -164,42 +287,56 @@ impl Row {
     }
 
     pub fn split(&mut self, at: usize) -> Self {
-        let mut row = String::new();
-        let mut length = 0;
-        let mut new_row: String = String::new();
-        let mut new_length = 0;
-        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
-            if index < at {
-                length += 1;
-                row.push_str(grapheme);
-            } else {
-                new_row.push_str(grapheme);
-                new_length += 1;
-            }
-        }
-        self.string = row;
-        self.len = length;
+        let at = at.min(self.len);
+        let new_row = self.string.split_off(self.byte_index(at));
+        let new_length = self.len - at;
+        self.len = at;
         self.is_highlighted = false;
         Self {
             string: new_row,
             len: new_length,
             is_highlighted: false,
+            hl_generation: 0,
+            starts_in_comment: false,
+            ends_in_comment: false,
             highlight: Vec::new()
         }
     }
 
-    pub fn get_prefix_len(&self, s: &str) -> usize {
-        let mut n = 0;
-        for grapheme in  self.string[..].graphemes(true) {
-            if grapheme != s {
-                break;
-            }
-            n += 1;
+    ///The spaces and tabs at the start of the row.
+    pub fn indent(&self) -> &str {
+        let rest = self.string.trim_start_matches([' ', '\t']);
+        &self.string[..self.string.len() - rest.len()]
+    }
+
+    ///Highlights the row, starting inside a multiline comment if `start_with_comment` is
+    ///set, and returns whether it ends inside one. The result is cached, so the row is only
+    ///tokenized again once it changed, when the state it starts in did, or when it was
+    ///highlighted in another search match `generation` than this one.
+    pub fn highlight(&mut self, hl: &HighlightOptions, word: &Option<String>, start_with_comment: bool,
+        generation: u64) -> bool {
+        if self.is_highlighted && self.hl_generation == generation
+            && self.starts_in_comment == start_with_comment {
+            return self.ends_in_comment;
         }
-        n
+        self.starts_in_comment = start_with_comment;
+        self.ends_in_comment = self.tokenize(hl, word, start_with_comment);
+        self.is_highlighted = true;
+        self.hl_generation = generation;
+        self.ends_in_comment
     }
 
-    pub fn highlight(&mut self, hl: &HighlightOptions, word: &Option<String>, start_with_comment: bool) -> bool {
+    ///Whether the row started inside a multiline comment when it was last highlighted.
+    pub fn starts_in_comment(&self) -> bool {
+        self.starts_in_comment
+    }
+
+    ///Whether the row ended inside a multiline comment when it was last highlighted.
+    pub fn ends_in_comment(&self) -> bool {
+        self.ends_in_comment
+    }
+
+    fn tokenize(&mut self, hl: &HighlightOptions, word: &Option<String>, start_with_comment: bool) -> bool {
         let chars: Vec<char> = self.string.chars().collect();
         self.highlight = Vec::new();
         let mut index = 0;
@@ -456,4 +593,23 @@ impl Row {
 
     }
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delete_recounts_graphemes_that_join_up() {
+        //two regional indicators make a single flag once the X between them is gone
+        let mut row = Row::from("\u{1f1fa}X\u{1f1f8}");
+        assert_eq!(row.len(), 3);
+        row.delete(1);
+        assert_eq!(row.as_str(), "\u{1f1fa}\u{1f1f8}");
+        assert_eq!(row.len(), 1);
+        row.delete(1);
+        assert_eq!(row.len(), 1);
+        row.delete(0);
+        assert_eq!(row.len(), 0);
+    }
+}
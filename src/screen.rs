@@ -1,10 +1,14 @@
-use termion::event::Key;
+use termion::color;
 use termion::raw::{IntoRawMode, RawTerminal};
-use termion::input::TermRead;
-use std::io::{stdout, stdin, Write};
+use std::io::{stdout, Write};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 
 const RESERVED_ROWS : u16 = 2 ; 
+//unchanged cells between two changed spans are rewritten rather than jumped over when
+//there are fewer than this, since moving the cursor costs about as much
+const MAX_GAP: usize = 4;
 
 
 #[derive(Default, Clone)]
@@ -13,14 +17,73 @@ pub struct Position {
     pub y: usize,
 }
 
+///How the text of a cell is drawn.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Style {
+    pub fg: Option<color::Rgb>,
+    pub bg: Option<color::Rgb>,
+    pub inverted: bool,
+}
+
+///One column of the terminal: a grapheme and its style. A wide grapheme is followed by
+///cells with an empty symbol for the other columns it covers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cell {
+    pub symbol: String,
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self { symbol: String::from(" "), style: Style::default() }
+    }
+}
+
+///Cells showing `text` in `style`, one per column.
+pub fn cells(text: &str, style: Style) -> Vec<Cell> {
+    let mut cells = Vec::new();
+    for grapheme in text.graphemes(true) {
+        let symbol = if is_invisible(grapheme) { " " } else { grapheme };
+        cells.push(Cell { symbol: symbol.to_string(), style });
+        for _ in 1..display_width(grapheme) {
+            cells.push(Cell { symbol: String::new(), style });
+        }
+    }
+    cells
+}
+
+///Columns of the terminal `grapheme` takes: two for wide ones such as CJK and most emoji,
+///and one for the rest, including those drawn as nothing at all.
+pub fn display_width(grapheme: &str) -> usize {
+    grapheme.width().clamp(1, 2)
+}
+
+///Whether the terminal draws `grapheme` as nothing, like a zero width space. We give
+///those a column anyway, so that the cursor can be put on them.
+pub fn is_invisible(grapheme: &str) -> bool {
+    grapheme.width() == 0
+}
+
+///Columns of the terminal `text` takes.
+pub fn text_width(text: &str) -> usize {
+    text.graphemes(true).map(display_width).sum()
+}
+
 pub struct ScreenSize {
     pub width: u16,
     pub height: u16
 }
 
+///The terminal. Lines are drawn into a frame, and `present` then updates the terminal with
+///what changed since the previous frame.
 pub struct Screen {
     size: ScreenSize,
     _stdout: RawTerminal<std::io::Stdout>, //restore terminal state after exit
+    frame: Vec<Vec<Cell>>,
+    //what the terminal shows, `None` when it is unknown and has to be redrawn from scratch
+    shown: Option<Vec<Vec<Cell>>>,
+    //where the cursor was left by the last `present`
+    cursor: (usize, usize),
 }
 
 impl Screen {
@@ -30,57 +93,143 @@ impl Screen {
         Ok(Self {
             size: ScreenSize { width: xsize, height: ysize.saturating_sub(RESERVED_ROWS) },
             _stdout: stdout().into_raw_mode()?,
+            frame: vec![Vec::new(); ysize as usize],
+            shown: None,
+            cursor: (0, 0),
         })
     }
 
-    pub fn clear() {
-        print!("{}", termion::clear::All);
+    ///Picks up the new size of the terminal after it was resized. What it shows is
+    ///unknown from then on, so the next frame is drawn from scratch.
+    pub fn resize(&mut self) -> Result<(), std::io::Error> {
+        let (xsize, ysize) = termion::terminal_size()?;
+        self.size = ScreenSize { width: xsize, height: ysize.saturating_sub(RESERVED_ROWS) };
+        self.frame = vec![Vec::new(); ysize as usize];
+        self.shown = None;
+        Ok(())
     }
 
-    pub fn flush() -> Result <(), std::io::Error> {
+    ///Clears the terminal and leaves the cursor at the top left.
+    pub fn clear(&mut self) -> Result<(), std::io::Error> {
+        self.shown = None;
+        print!("{}{}", termion::clear::All, termion::cursor::Goto(1, 1));
         stdout().flush()
     }
 
-    pub fn read_key() -> Result<Key, std::io::Error> {
-        loop {
-            if let Some(key) = stdin().lock().keys().next() {
-                return key;
+    ///Sets line `y` of the next frame, cutting it at the width of the terminal.
+    pub fn draw_line(&mut self, y: usize, mut line: Vec<Cell>) {
+        let width = self.size.width as usize;
+        if line.get(width).is_some_and(|cell| cell.symbol.is_empty()) {
+            //a wide grapheme cut by the right edge would spill over, blank it instead
+            let lead = line[..width].iter().rposition(|cell| !cell.symbol.is_empty()).unwrap_or(0);
+            for cell in &mut line[lead..width] {
+                cell.symbol = String::from(" ");
             }
         }
+        line.truncate(width);
+        if let Some(frame_line) = self.frame.get_mut(y) {
+            *frame_line = line;
+        }
     }
 
-    pub fn clear_current_line() {
-        print!("{}", termion::clear::CurrentLine)
+    ///Brings the terminal up to date with the frame in a single write, only touching the
+    ///cells that changed, and puts the cursor at `cursor`.
+    pub fn present(&mut self, cursor: &Position) -> Result<(), std::io::Error> {
+        let width = self.size.width as usize;
+        let blank = Cell::default();
+        let mut out = termion::cursor::Hide.to_string();
+        if self.shown.is_none() {
+            out.push_str(termion::clear::All.as_ref());
+        }
+        let no_lines = Vec::new();
+        let shown = self.shown.as_ref().unwrap_or(&no_lines);
+        let mut style = None;
+        //where the terminal cursor is, when we know
+        let mut at = None;
+        for (y, line) in self.frame.iter().enumerate() {
+            let old = shown.get(y).map_or(&[][..], Vec::as_slice);
+            let unchanged = |x: usize| line.get(x).unwrap_or(&blank) == old.get(x).unwrap_or(&blank);
+            let end = line.len().min(width);
+            let mut x = 0;
+            while x < end {
+                if unchanged(x) {
+                    x += 1;
+                    continue;
+                }
+                let mut span_end = x + 1;
+                let mut next = span_end;
+                while next < end && next - span_end < MAX_GAP {
+                    if !unchanged(next) {
+                        span_end = next + 1;
+                    }
+                    next += 1;
+                }
+                //spans hold whole graphemes, so that we know where the terminal cursor ends up
+                while x > 0 && line[x].symbol.is_empty() {
+                    x -= 1;
+                }
+                while span_end < end && line[span_end].symbol.is_empty() {
+                    span_end += 1;
+                }
+                move_to(&mut out, &mut at, x, y);
+                for cell in &line[x..span_end] {
+                    set_style(&mut out, &mut style, cell.style);
+                    out.push_str(&cell.symbol);
+                }
+                at = Some((span_end, y));
+                x = span_end;
+            }
+            //whatever is left of the old line goes in one go
+            if old.len() > end {
+                move_to(&mut out, &mut at, end, y);
+                set_style(&mut out, &mut style, Style::default());
+                out.push_str(termion::clear::UntilNewline.as_ref());
+            }
+        }
+        let blank_frame = vec![Vec::new(); self.frame.len()];
+        let unchanged = at.is_none() && self.shown.is_some() && self.cursor == (cursor.x, cursor.y);
+        self.shown = Some(std::mem::replace(&mut self.frame, blank_frame));
+        self.cursor = (cursor.x, cursor.y);
+        if unchanged {
+            //nothing to do, e.g. when a timer woke us up
+            return Ok(());
+        }
+        out.push_str(termion::style::Reset.as_ref());
+        out.push_str(&termion::cursor::Goto(cursor.x as u16 + 1, cursor.y as u16 + 1).to_string());
+        out.push_str(termion::cursor::Show.as_ref());
+
+        let mut stdout = stdout();
+        stdout.write_all(out.as_bytes())?;
+        stdout.flush()?;
+        Ok(())
     }
 
     pub fn size(&self) -> &ScreenSize {
         &self.size
     }
+}
 
-    pub fn cursor_position(position: &Position){
-        let Position {mut x, mut y} = position;
-        x = x.saturating_add(1); //terminal cursor position is 1-indexed
-        y = y.saturating_add(1);
-        let x = x as u16;
-        let y = y as u16;
-        print!("{}", termion::cursor::Goto(x, y));
+fn move_to(out: &mut String, at: &mut Option<(usize, usize)>, x: usize, y: usize) {
+    if *at != Some((x, y)) {
+        out.push_str(&termion::cursor::Goto(x as u16 + 1, y as u16 + 1).to_string());
+        *at = Some((x, y));
     }
+}
 
-
-
-    pub fn cursor_show() {
-        print!("{}", termion::cursor::Show);
+fn set_style(out: &mut String, current: &mut Option<Style>, style: Style) {
+    if *current == Some(style) {
+        return;
     }
-
-    pub fn cursor_hide() {
-        print!("{}", termion::cursor::Hide);
+    out.push_str(termion::style::Reset.as_ref());
+    match style.fg {
+        Some(fg) => out.push_str(&color::Fg(fg).to_string()),
+        None => out.push_str(&color::Fg(color::Reset).to_string()),
     }
-
-    pub fn invert_colors(){
-        print!("{}", termion::style::Invert);
+    if let Some(bg) = style.bg {
+        out.push_str(&color::Bg(bg).to_string());
     }
-
-    pub fn reset_all_formatting(){
-        print!("{}", termion::style::Reset);
+    if style.inverted {
+        out.push_str(termion::style::Invert.as_ref());
     }
-}
\ No newline at end of file
+    *current = Some(style);
+}
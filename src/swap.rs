@@ -0,0 +1,75 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+
+const SWAP_HEADER: &str = "hecto swap";
+
+///Unsaved contents of a buffer, written next to the file so that they survive a crash.
+pub struct Swap {
+    pub pid: u32,
+    pub text: String,
+}
+
+impl Swap {
+    ///Whether the process that wrote this swap file is still running, in which case the
+    ///file is probably open in another hecto.
+    pub fn is_live(&self) -> bool {
+        if self.pid == process::id() {
+            return false;
+        }
+        let pid = self.pid as libc::pid_t;
+        //signal 0 only checks whether the process exists
+        let alive = unsafe { libc::kill(pid, 0) } == 0;
+        alive || Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+}
+
+///The swap file of `path`, a hidden `.name.hecto-swp` in the same directory.
+pub fn path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.hecto-swp", name))
+}
+
+///Writes `text` to the swap file at `path`. It goes to a temporary file first, so a crash
+///halfway leaves the previous swap file whole, and only the user can read it, since it
+///holds the contents of the file.
+pub fn write(path: &Path, text: &str) -> Result<(), Error> {
+    let contents = format!("{} {}\n{}", SWAP_HEADER, process::id(), text);
+    let mut tmp_name = path.as_os_str().to_os_string();
+    tmp_name.push(format!(".{}.tmp", process::id()));
+    let tmp_path = PathBuf::from(tmp_name);
+    //left behind by a crash, its permissions can't be trusted
+    let _ = fs::remove_file(&tmp_path);
+
+    let result = (|| {
+        let mut tmp = fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(&tmp_path)?;
+        tmp.write_all(contents.as_bytes())?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+///Reads the swap file at `path`, if there is one.
+pub fn read(path: &Path) -> Result<Option<Swap>, Error> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let (header, text) = contents.split_once('\n').unwrap_or((&contents, ""));
+    let pid = header
+        .strip_prefix(SWAP_HEADER)
+        .and_then(|pid| pid.trim().parse().ok())
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "not a hecto swap file"))?;
+    Ok(Some(Swap { pid, text: text.to_string() }))
+}
+
+pub fn remove(path: &Path) {
+    let _ = fs::remove_file(path);
+}